@@ -0,0 +1,2 @@
+pub mod state_pattern;
+pub mod template_method_pattern;
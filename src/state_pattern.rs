@@ -1,19 +1,160 @@
-use std::collections::HashMap;
-use std::{fmt, thread};
-use std::sync::mpsc;
-use std::sync::mpsc::Sender;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::{Sender, TryRecvError};
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug,Clone)]
+pub mod actor;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod attribution;
+pub mod clock;
+pub mod config;
+pub mod diagram;
+pub mod engine;
+pub mod events;
+pub mod journal;
+pub mod liquidation;
+pub mod machine;
+pub mod monte_carlo;
+pub mod notifier;
+pub mod policy;
+pub mod position_book;
+pub mod pre_trade;
+pub mod recovery;
+pub mod replay;
+pub mod snapshot;
+pub mod var_engine;
+
+use attribution::VarAttribution;
+use clock::{Clock, SystemClock};
+use config::{ConfigError, ConfigWatcher, RiskConfig, RiskLimits};
+pub use clock::MockClock;
+pub use engine::{CommandSink, EngineReport, EngineStatus, ReportSource, TradingEngine, DEFAULT_ENGINE};
+use engine::EngineEndpoint;
+use liquidation::{GreedyLiquidation, LiquidationOrder, LiquidationPlan, LiquidationStrategy};
+use diagram::Diagram;
+use machine::{Guard, StateMachine};
+use events::{EventBus, RiskEvent, RiskEventKind, RiskEventSubscriber, SubscriptionId};
+use notifier::{Alert, ConsoleNotifier, Notifier, Severity};
+use policy::TransitionPolicy;
+use position_book::{BookEvent, EventLog, RecordedEvent};
+use pre_trade::{OrderCheck, OrderVerdict};
+use replay::Transition;
+use recovery::{OperatorAuthenticator, ResetError, ResetRequest, StaticAuthenticator};
+use snapshot::{Checkpointer, RiskSnapshot};
+use var_engine::{MetricLimits, RiskMeasures, RiskMetric, SummedVar, VarEngine, VarError};
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum TradingEngineCommand {
     ExecuteTrade,
     NoTrade,
     StopEngine,
+    /// Only orders that reduce existing positions may be sent.
+    ReduceOnly,
+    Throttle(ThrottleLimits),
+    CancelAllOrders,
+    Liquidate(Vec<LiquidationOrder>),
+    /// Lifts any restriction set by an earlier command.
+    Resume,
+    /// Liveness check from the watchdog; the engine answers with `EngineReport::Heartbeat`.
+    Heartbeat,
 }
+
+/// Shutdown is entered at 120% of a limit unless configured otherwise.
+pub(crate) const DEFAULT_SHUTDOWN_MULTIPLIER: f64 = 1.2;
+/// How many transitions `RiskManager::diagram` highlights.
+const RECENT_TRANSITIONS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrottleLimits {
+    pub max_order_size: f64,
+    pub max_orders_per_second: u32,
+}
+
+impl Default for ThrottleLimits {
+    fn default() -> Self {
+        ThrottleLimits { max_order_size: 1_000.0, max_orders_per_second: 10 }
+    }
+}
+
+/// A `TradingEngineCommand` as it travels to the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandEnvelope {
+    pub sequence: u64,
+    pub timestamp: SystemTime,
+    pub origin: StateKind,
+    pub command: TradingEngineCommand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StateKind {
+    Normal,
+    Warning,
+    Recovery,
+    LimitBreach,
+    Shutdown,
+    /// The watchdog lost contact with the trading engine; nothing it does can be confirmed.
+    EngineUnavailable,
+}
+
+impl StateKind {
+    /// Every state, from least to most severe.
+    pub const ALL: [StateKind; 6] = [
+        StateKind::Normal,
+        StateKind::Warning,
+        StateKind::Recovery,
+        StateKind::LimitBreach,
+        StateKind::Shutdown,
+        StateKind::EngineUnavailable,
+    ];
+}
+
+impl fmt::Display for StateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateKind::Normal => write!(f, "Normal Operation"),
+            StateKind::Warning => write!(f, "Warning Level"),
+            StateKind::Recovery => write!(f, "Recovery"),
+            StateKind::LimitBreach => write!(f, "Limit Breach"),
+            StateKind::Shutdown => write!(f, "Shutdown"),
+            StateKind::EngineUnavailable => write!(f, "Engine Unavailable"),
+        }
+    }
+}
+
+/// Parses the variant name, as `{:?}` prints it.
+impl FromStr for StateKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<StateKind, String> {
+        StateKind::ALL
+            .iter()
+            .copied()
+            .find(|state| format!("{:?}", state) == name)
+            .ok_or_else(|| format!("unknown state {}", name))
+    }
+}
+
 pub trait RiskState: fmt::Debug {
+    fn kind(&self) -> StateKind;
     fn check_var(&self, context: &RiskManager) -> Option<Box<dyn RiskState>>;
     fn enter_state(&self, context: &RiskManager);
     fn exit_state(&self, context: &RiskManager);
     fn send_command(&self, context: &RiskManager);
+    /// Whether LimitBreach was reached by a Warning timing out; kept in snapshots.
+    fn escalated(&self) -> bool {
+        false
+    }
+    /// The state EngineUnavailable was entered from; kept in snapshots.
+    fn resumes_to(&self) -> Option<StateKind> {
+        None
+    }
+    /// Index of the machine rule this state was entered through.
+    fn entered_by(&self) -> Option<usize> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -21,209 +162,1000 @@ pub struct RiskManager {
     state: Box<dyn RiskState>,
     pub var_limit: f64,
     pub warning_level: f64,
+    pub shutdown_multiplier: f64,
     pub current_var: f64,
-    pub positions: HashMap<String, f64>, // Position ID -> VaR contribution
-    trading_engine_sender: Sender<TradingEngineCommand>,
+    pub positions: HashMap<String, f64>, // Position ID -> size (the VaR contribution itself under SummedVar)
+    pub risk_measures: RiskMeasures,
+    pub es_limits: Option<MetricLimits>,
+    pub throttle: ThrottleLimits,
+    engines: RefCell<BTreeMap<String, EngineEndpoint>>,
+    next_sequence: Cell<u64>,
+    ack_timeout: Duration,
+    heartbeat_interval: Option<Duration>,
+    engine_timeout: Duration,
+    last_heartbeat: SystemTime,
+    /// Engine liveness as the watchdog last saw it.
+    engines_alive: bool,
+    /// Set by replay, which takes engine liveness from the log rather than from the engines.
+    replayed_liveness: Option<bool>,
+    notifier: Box<dyn Notifier>,
+    events: EventBus,
+    policy: TransitionPolicy,
+    state_entered_at: SystemTime,
+    authenticator: Box<dyn OperatorAuthenticator>,
+    clock: Box<dyn Clock>,
+    var_engine: Box<dyn VarEngine>,
+    liquidation_strategy: Box<dyn LiquidationStrategy>,
+    checkpoints: Option<Checkpointer>,
+    event_log: Option<Box<dyn EventLog>>,
+    config: Option<ConfigWatcher>,
+    machine: StateMachine,
+    recent_transitions: VecDeque<Transition>,
+    #[cfg(feature = "async")]
+    entry_hooks: Option<asynchronous::EntryHooks>,
 }
 
 impl RiskManager {
-    pub fn new(var_limit: f64, warning_level: f64, trading_engine_sender: Sender<TradingEngineCommand>) -> Self {
-        let mut manager = RiskManager {
-            state: Box::new(NormalOperationState{cmd: TradingEngineCommand::ExecuteTrade}),
+    /// # Panics
+    ///
+    /// If the limits are inconsistent, see `RiskLimits::validate`.
+    pub fn new(var_limit: f64, warning_level: f64, trading_engine_sender: Sender<CommandEnvelope>) -> Self {
+        let manager = RiskManager {
+            state: Box::new(DeclaredState{kind: StateKind::Normal, escalated: false, resume: None, rule: None}),
             var_limit,
             warning_level,
+            shutdown_multiplier: DEFAULT_SHUTDOWN_MULTIPLIER,
             current_var: 0.0,
             positions: HashMap::new(),
-            trading_engine_sender,
+            risk_measures: RiskMeasures::default(),
+            es_limits: None,
+            throttle: ThrottleLimits::default(),
+            engines: RefCell::new(BTreeMap::from([(
+                DEFAULT_ENGINE.to_string(),
+                EngineEndpoint::new(Box::new(trading_engine_sender), SystemTime::now()),
+            )])),
+            next_sequence: Cell::new(1),
+            ack_timeout: Duration::from_secs(5),
+            heartbeat_interval: None,
+            engine_timeout: Duration::from_secs(5),
+            last_heartbeat: SystemTime::now(),
+            engines_alive: true,
+            replayed_liveness: None,
+            notifier: Box::new(ConsoleNotifier),
+            events: EventBus::new(),
+            policy: TransitionPolicy::default(),
+            state_entered_at: SystemTime::now(),
+            authenticator: Box::new(StaticAuthenticator::new()),
+            clock: Box::new(SystemClock),
+            var_engine: Box::new(SummedVar),
+            liquidation_strategy: Box::new(GreedyLiquidation),
+            checkpoints: None,
+            event_log: None,
+            config: None,
+            machine: StateMachine::default(),
+            recent_transitions: VecDeque::new(),
+            #[cfg(feature = "async")]
+            entry_hooks: None,
         };
+        if let Err(err) = manager.limits().validate() {
+            panic!("{}", err);
+        }
+        manager
+    }
+
+    pub fn from_config(config: &RiskConfig, trading_engine_sender: Sender<CommandEnvelope>) -> Self {
+        let mut manager = RiskManager::new(config.var_limit, config.warning_level(), trading_engine_sender);
+        manager.apply_config(config);
         manager
     }
 
+    /// Takes limits and alert routing from the config at `path`, and re-reads it from `tick`
+    /// whenever the file changes.
+    pub fn with_config_file(mut self, path: impl Into<std::path::PathBuf>) -> Result<Self, ConfigError> {
+        let watcher = ConfigWatcher::new(path.into());
+        let config = RiskConfig::load(&watcher.path)?;
+        self.apply_config(&config);
+        self.config = Some(watcher);
+        Ok(self)
+    }
+
+    pub fn with_notifier(mut self, notifier: Box<dyn Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Fails if the policy's hysteresis bands do not fit the limits, see `RiskLimits::validate`.
+    pub fn with_policy(mut self, policy: TransitionPolicy) -> Result<Self, ConfigError> {
+        self.policy = policy;
+        self.limits().validate()?;
+        Ok(self)
+    }
+
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.state_entered_at = clock.now();
+        self.last_heartbeat = clock.now();
+        for engine in self.engines.get_mut().values_mut() {
+            engine.last_contact = clock.now();
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// Adds Expected Shortfall limits; the state machine then follows whichever of VaR and ES is worse.
+    pub fn with_es_limits(mut self, warning_level: f64, limit: f64) -> Self {
+        self.es_limits = Some(MetricLimits { warning_level, limit });
+        self
+    }
+
+    /// Listens for acknowledgements and fills from the engine passed to `new`. Commands not
+    /// acknowledged within `ack_timeout`, by any engine, are escalated on the next `tick`.
+    pub fn with_engine_reports(mut self, reports: impl ReportSource + 'static, ack_timeout: Duration) -> Self {
+        self.attach_reports(DEFAULT_ENGINE, reports);
+        self.ack_timeout = ack_timeout;
+        self
+    }
+
+    /// Sends a heartbeat to every engine each `heartbeat_interval` and moves to EngineUnavailable
+    /// when nothing has been heard back from one of them for `engine_timeout`. Needs engine reports.
+    pub fn with_watchdog(mut self, heartbeat_interval: Duration, engine_timeout: Duration) -> Self {
+        self.heartbeat_interval = Some(heartbeat_interval);
+        self.engine_timeout = engine_timeout;
+        self
+    }
+
+    /// Replaces the standard states and transitions. Build the machine with
+    /// `StateMachine::builder` or load it with `StateMachine::load`; both validate it.
+    pub fn with_state_machine(mut self, machine: StateMachine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    pub fn state_machine(&self) -> &StateMachine {
+        &self.machine
+    }
+
+    /// The state machine with the current state and the last few transitions highlighted.
+    pub fn diagram(&self) -> Diagram<'_> {
+        let path: Vec<Transition> = self.recent_transitions.iter().copied().collect();
+        Diagram::new(&self.machine).with_current(self.state.kind()).with_path(&path)
+    }
+
+    pub fn with_throttle(mut self, throttle: ThrottleLimits) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    pub fn with_var_engine(mut self, var_engine: Box<dyn VarEngine>) -> Self {
+        self.var_engine = var_engine;
+        self
+    }
+
+    pub fn with_liquidation_strategy(mut self, strategy: Box<dyn LiquidationStrategy>) -> Self {
+        self.liquidation_strategy = strategy;
+        self
+    }
+
+    /// Writes a snapshot to `path` on every transition and, from `tick`, whenever `interval` has passed.
+    pub fn with_checkpoints(mut self, path: impl Into<std::path::PathBuf>, interval: Duration) -> Self {
+        self.checkpoints = Some(Checkpointer { path: path.into(), interval, last: None });
+        self
+    }
+
+    /// Resumes from `snapshot`: book, limits and state are taken over as they were, including
+    /// when the state was entered, so dwell and escalation timers carry on. The state's entry
+    /// actions (alerts, cancelling orders, liquidating) are not repeated; only its standing
+    /// command is sent again, so a restarted engine learns the current restriction.
+    ///
+    /// Apply it after the other builders, so VaR is recomputed with the configured engine.
+    /// Fails, leaving nothing restored, if the snapshot's limits do not pass `RiskLimits::validate`.
+    pub fn restore(mut self, snapshot: &RiskSnapshot) -> Result<Self, ConfigError> {
+        self.apply_restore(snapshot)?;
+        Ok(self)
+    }
+
+    /// `restore` on a manager in place, also used by replay.
+    pub(crate) fn apply_restore(&mut self, snapshot: &RiskSnapshot) -> Result<(), ConfigError> {
+        snapshot.limits().validate()?;
+        self.record(BookEvent::Restored { snapshot: snapshot.clone() });
+        self.var_limit = snapshot.var_limit;
+        self.warning_level = snapshot.warning_level;
+        self.shutdown_multiplier = snapshot.shutdown_multiplier;
+        self.policy.warning_band = snapshot.warning_band;
+        self.policy.limit_band = snapshot.limit_band;
+        self.es_limits = snapshot.es_limits;
+        self.throttle = snapshot.throttle;
+        self.next_sequence.set(self.next_sequence.get().max(snapshot.next_sequence));
+        self.state = self.state_for(snapshot.state, snapshot.escalated, snapshot.resume);
+        self.state_entered_at = snapshot.state_entered_at;
+        match self.var_engine.measure(&self.positions) {
+            Ok(measures) => {
+                self.risk_measures = measures;
+                self.current_var = measures.var;
+            }
+            Err(err) => self.notify(Severity::Critical, "VaR calculation failed", &err.to_string()),
+        }
+        self.publish(RiskEventKind::Restored { taken_at: snapshot.taken_at });
+        self.send_command();
+        Ok(())
+    }
+
+    fn state_for(&self, kind: StateKind, escalated: bool, resume: Option<StateKind>) -> Box<dyn RiskState> {
+        Box::new(DeclaredState::new(kind, escalated, resume))
+    }
+
+    /// The state the machine's `rule`-th transition leads to.
+    fn state_via(&self, rule: usize, resume: Option<StateKind>) -> Box<dyn RiskState> {
+        let transition = &self.machine.transitions()[rule];
+        let state = DeclaredState::new(transition.to, transition.escalates, resume);
+        Box::new(DeclaredState { rule: Some(rule), ..state })
+    }
+
+    pub fn snapshot(&self) -> RiskSnapshot {
+        RiskSnapshot {
+            taken_at: self.now(),
+            state: self.state.kind(),
+            state_entered_at: self.state_entered_at,
+            escalated: self.state.escalated(),
+            resume: self.state.resumes_to(),
+            var_limit: self.var_limit,
+            warning_level: self.warning_level,
+            shutdown_multiplier: self.shutdown_multiplier,
+            warning_band: self.policy.warning_band,
+            limit_band: self.policy.limit_band,
+            es_limits: self.es_limits,
+            throttle: self.throttle,
+            positions: self.positions.iter().map(|(id, size)| (id.clone(), *size)).collect(),
+            next_sequence: self.next_sequence.get(),
+        }
+    }
+
+    /// Writes a snapshot now, if checkpoints are configured.
+    pub fn checkpoint(&mut self) {
+        let path = match &self.checkpoints {
+            Some(checkpoints) => checkpoints.path.clone(),
+            None => return,
+        };
+        let snapshot = self.snapshot();
+        if let Err(err) = snapshot.save(&path) {
+            self.notify(Severity::Warning, "Checkpoint failed", &format!("Could not write {}: {}", path.display(), err));
+        }
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.last = Some(snapshot.taken_at);
+        }
+    }
+
+    fn checkpoint_if_due(&mut self) {
+        let now = self.now();
+        let due = self.checkpoints.as_ref().is_some_and(|checkpoints| {
+            checkpoints.last.is_none_or(|last| now.duration_since(last).unwrap_or_default() >= checkpoints.interval)
+        });
+        if due {
+            self.checkpoint();
+        }
+    }
+
+    /// Records every change to the book, and every tick that moved the state machine, so
+    /// `replay::replay` can reproduce the run offline.
+    pub fn with_event_log(mut self, event_log: Box<dyn EventLog>) -> Self {
+        self.event_log = Some(event_log);
+        // Attached after a restore or once trading started, the log begins from what is there.
+        if !self.positions.is_empty() || self.state.kind() != StateKind::Normal {
+            let snapshot = self.snapshot();
+            self.record(BookEvent::Restored { snapshot });
+        }
+        self
+    }
+
+    /// The only way the book changes: the event is logged, then applied.
+    fn record(&mut self, event: BookEvent) -> bool {
+        if let Some(event_log) = self.event_log.as_mut() {
+            event_log.record(&RecordedEvent { at: self.clock.now(), event: event.clone() });
+        }
+        event.apply(&mut self.positions)
+    }
+
+    pub fn limits(&self) -> RiskLimits {
+        RiskLimits {
+            var_limit: self.var_limit,
+            warning_level: self.warning_level,
+            shutdown_multiplier: self.shutdown_multiplier,
+            warning_band: self.policy.warning_band,
+            limit_band: self.policy.limit_band,
+        }
+    }
+
+    /// Switches to new limits and re-evaluates the current state against them. Dwell times
+    /// still hold back de-escalations.
+    pub fn set_limits(&mut self, limits: RiskLimits) -> Result<(), ConfigError> {
+        limits.validate()?;
+        self.apply_limits(limits);
+        Ok(())
+    }
+
+    /// `set_limits` without validation, for limits that were validated when first set.
+    pub(crate) fn apply_limits(&mut self, limits: RiskLimits) {
+        let previous = self.limits();
+        if limits == previous {
+            return;
+        }
+        self.record(BookEvent::LimitsChanged { limits });
+        self.var_limit = limits.var_limit;
+        self.warning_level = limits.warning_level;
+        self.shutdown_multiplier = limits.shutdown_multiplier;
+        self.policy.warning_band = limits.warning_band;
+        self.policy.limit_band = limits.limit_band;
+        self.publish(RiskEventKind::LimitsChanged { previous, limits });
+        let before = self.state.kind();
+        self.check_state();
+        if self.state.kind() != before {
+            self.send_command();
+        }
+    }
+
+    /// Applies a validated config: its limits and its alert routing. Without routes, alerts go
+    /// to the console, so that removing them from the file takes effect too.
+    pub fn apply_config(&mut self, config: &RiskConfig) {
+        self.notifier = config.notifier().unwrap_or_else(|| Box::new(ConsoleNotifier));
+        self.apply_limits(config.limits());
+    }
+
+    /// Re-reads the config file if it changed since it was last read. An unreadable or invalid
+    /// file is reported and the limits in force are kept. Returns whether a new config was applied.
+    pub fn reload_config(&mut self) -> Result<bool, ConfigError> {
+        let path = match self.config.as_mut().and_then(ConfigWatcher::poll) {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        match RiskConfig::load(&path) {
+            Ok(config) => {
+                self.apply_config(&config);
+                Ok(true)
+            }
+            Err(err) => {
+                let message = format!("Keeping the current limits, {} could not be applied: {}", path.display(), err);
+                self.notify(Severity::Warning, "Config reload failed", &message);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn with_authenticator(mut self, authenticator: Box<dyn OperatorAuthenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    pub fn notify(&self, severity: Severity, subject: &str, message: &str) {
+        let (trigger, trigger_value, trigger_limit) = self.trigger();
+        let alert = Alert {
+            severity,
+            subject: subject.to_string(),
+            message: message.to_string(),
+            current_var: self.current_var,
+            var_limit: self.var_limit,
+            warning_level: self.warning_level,
+            trigger,
+            trigger_value,
+            trigger_limit,
+            timestamp: self.now(),
+        };
+        if let Err(err) = self.notifier.notify(&alert) {
+            eprintln!("Failed to deliver risk alert '{}': {}", alert.subject, err);
+        }
+    }
+
+    pub fn subscribe(&mut self, subscriber: Box<dyn RiskEventSubscriber>) -> SubscriptionId {
+        self.events.subscribe(subscriber)
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    pub fn state(&self) -> StateKind {
+        self.state.kind()
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn time_in_state(&self) -> Duration {
+        self.now().duration_since(self.state_entered_at).unwrap_or_default()
+    }
+
+    /// Every metric with limits in force, as (metric, value, warning level, limit).
+    fn readings(&self) -> Vec<(RiskMetric, f64, f64, f64)> {
+        self.readings_for(&self.risk_measures)
+    }
+
+    fn readings_for(&self, measures: &RiskMeasures) -> Vec<(RiskMetric, f64, f64, f64)> {
+        let mut readings = vec![(RiskMetric::Var, measures.var, self.warning_level, self.var_limit)];
+        if let (Some(es), Some(limits)) = (measures.expected_shortfall, self.es_limits) {
+            readings.push((RiskMetric::ExpectedShortfall, es, limits.warning_level, limits.limit));
+        }
+        readings
+    }
+
+    /// The metric using the largest share of its limit, with its value and limit.
+    pub fn trigger(&self) -> (RiskMetric, f64, f64) {
+        self.readings()
+            .into_iter()
+            .map(|(metric, value, _, limit)| (metric, value, limit))
+            .max_by(|a, b| (a.1 / a.2).total_cmp(&(b.1 / b.2)))
+            .unwrap_or((RiskMetric::Var, self.current_var, self.var_limit))
+    }
+
+    pub fn at_warning(&self) -> bool {
+        self.readings().iter().any(|(_, value, warning, _)| value >= warning)
+    }
+
+    pub fn at_limit(&self) -> bool {
+        self.readings().iter().any(|(_, value, _, limit)| value >= limit)
+    }
+
+    /// Hysteresis bands are expressed on the VaR scale and applied proportionally to the other metrics.
+    pub fn below_warning_exit(&self) -> bool {
+        let ratio = self.warning_exit_level() / self.warning_level;
+        self.readings().iter().all(|(_, value, warning, _)| *value < warning * ratio)
+    }
+
+    pub fn below_limit_exit(&self) -> bool {
+        let ratio = self.limit_exit_level() / self.var_limit;
+        self.readings().iter().all(|(_, value, _, limit)| *value < limit * ratio)
+    }
+
+    /// True once the current state has lasted longer than its escalation timeout, if it has one.
+    pub fn escalation_due(&self) -> bool {
+        self.policy
+            .escalate_after(self.state.kind())
+            .is_some_and(|after| self.time_in_state() >= after)
+    }
+
+    /// VaR must fall below this level before Warning gives way to Normal.
+    pub fn warning_exit_level(&self) -> f64 {
+        self.warning_level - self.policy.warning_band
+    }
+
+    /// VaR must fall below this level before LimitBreach is left.
+    pub fn limit_exit_level(&self) -> f64 {
+        self.var_limit - self.policy.limit_band
+    }
+
+    fn publish(&self, kind: RiskEventKind) {
+        self.events.publish(&self.event(kind));
+    }
+
+    /// The positions in a stable order, as events and journals carry them.
+    fn book(&self) -> BTreeMap<String, f64> {
+        self.positions.iter().map(|(id, size)| (id.clone(), *size)).collect()
+    }
+
+    fn event(&self, kind: RiskEventKind) -> RiskEvent {
+        RiskEvent {
+            timestamp: self.now(),
+            state: self.state.kind(),
+            current_var: self.current_var,
+            current_es: self.risk_measures.expected_shortfall,
+            var_limit: self.var_limit,
+            warning_level: self.warning_level,
+            kind,
+        }
+    }
+
+    /// Broadcasts `command` to every registered engine, under a single sequence number.
+    /// Engines scoped to some positions only receive the liquidation orders for those.
+    pub fn dispatch(&self, command: TradingEngineCommand) {
+        let sequence = self.next_sequence.get();
+        self.next_sequence.set(sequence + 1);
+        let now = self.now();
+        let mut lost = Vec::new();
+        for (engine_id, engine) in self.engines.borrow_mut().iter_mut() {
+            let command = match engine.scoped(&command) {
+                Some(command) => command,
+                None => continue,
+            };
+            let envelope = CommandEnvelope { sequence, timestamp: now, origin: self.state.kind(), command: command.clone() };
+            if engine.sender.deliver(envelope).is_err() {
+                if engine.connected {
+                    engine.connected = false;
+                    lost.push(engine_id.clone());
+                }
+                continue;
+            }
+            engine.delivered += 1;
+            if engine.reports.is_some() {
+                engine.pending_acks.insert(sequence, (now, command));
+            }
+        }
+        for engine_id in lost {
+            let message = format!("Failed to send {:?}: trading engine '{}' has stopped.", command, engine_id);
+            self.notify(Severity::Critical, "Trading engine unavailable", &message);
+        }
+        self.publish(RiskEventKind::CommandSent { sequence, command });
+    }
+
+    /// False once any engine's channel has closed, or the watchdog has not heard from it in time.
+    pub fn engine_alive(&self) -> bool {
+        if let Some(alive) = self.replayed_liveness {
+            return alive;
+        }
+        let now = self.now();
+        self.engines.borrow().values().all(|engine| {
+            engine.connected
+                && self.heartbeat_interval.is_none_or(|_| {
+                    now.duration_since(engine.last_contact).unwrap_or_default() < self.engine_timeout
+                })
+        })
+    }
+
     pub fn update_var(&mut self) {
-        self.current_var = self.positions.values().sum();
+        let previous_var = self.current_var;
+        match self.var_engine.measure(&self.positions) {
+            Ok(measures) => {
+                self.risk_measures = measures;
+                self.current_var = measures.var;
+                self.publish(RiskEventKind::VarUpdated { previous_var });
+            }
+            // Keep the last good figure rather than report a VaR we could not compute.
+            Err(err) => self.notify(Severity::Critical, "VaR calculation failed", &err.to_string()),
+        }
     }
 
     pub fn add_position(&mut self, position_id: &str, var_contribution: f64) {
-        self.positions.insert(position_id.to_string(), var_contribution);
+        self.record(BookEvent::PositionSet { position_id: position_id.to_string(), size: var_contribution });
+        self.publish(RiskEventKind::PositionAdded { position_id: position_id.to_string(), var_contribution });
         self.update_var();
         self.check_state();
         self.send_command();
     }
 
-    pub fn remove_position(&mut self, position_id: &str) {
-        self.positions.remove(position_id);
+    /// Recomputes VaR for an unchanged book, e.g. after market data or the covariance matrix moved.
+    /// The new figures are logged, since replay cannot recompute them from the book.
+    pub fn revalue(&mut self) {
         self.update_var();
+        let measures = self.risk_measures;
+        self.record(BookEvent::Revalued { measures });
         self.check_state();
         self.send_command();
     }
-    pub fn send_command(&self) {
-        self.state.send_command(&self);
+
+    /// `revalue` with the figures it logged, used by replay.
+    pub(crate) fn apply_revaluation(&mut self, measures: RiskMeasures) {
+        let previous_var = self.current_var;
+        self.record(BookEvent::Revalued { measures });
+        self.risk_measures = measures;
+        self.current_var = measures.var;
+        self.publish(RiskEventKind::VarUpdated { previous_var });
+        self.check_state();
+        self.send_command();
     }
-    pub fn check_state(&mut self) {
-        match self.state.check_var(self){
-            Some(state) => self.change_state(state),
-            None => (),
+
+    pub fn attribution(&self) -> Result<VarAttribution, VarError> {
+        self.var_engine.attribute(&self.positions)
+    }
+
+    /// Message text with the largest VaR contributors appended, so the recipients know what to cut.
+    pub fn with_top_contributors(&self, message: &str) -> String {
+        match self.attribution() {
+            Ok(attribution) if !attribution.positions.is_empty() => format!("{} {}", message, attribution.report(3)),
+            _ => message.to_string(),
         }
     }
 
-    pub fn change_state(&mut self, new_state: Box<dyn RiskState>) {
-        self.state.exit_state(self);
-        self.state = new_state;
-        self.state.enter_state(self);
+    /// Position cuts that would bring VaR back under the warning level.
+    pub fn liquidation_plan(&self) -> Result<LiquidationPlan, VarError> {
+        self.liquidation_strategy.plan(self.var_engine.as_ref(), &self.positions, self.warning_level)
     }
 
-    pub fn should_shutdown(&self) -> bool {
-        self.current_var >= self.var_limit * 1.2
+    /// Pre-trade check: the risk and state the book would have if `position_id` were set to `size`,
+    /// as `add_position` would do. The book itself is left untouched.
+    ///
+    /// The projected state is classified on the raw thresholds, without hysteresis or dwell times.
+    pub fn check_order(&self, position_id: &str, size: f64) -> Result<OrderCheck, VarError> {
+        let mut book = self.positions.clone();
+        book.insert(position_id.to_string(), size);
+        let projected = self.var_engine.measure(&book)?;
+        let readings = self.readings_for(&projected);
+        let projected_state = if readings.iter().any(|(_, value, _, limit)| *value >= limit * self.shutdown_multiplier) {
+            StateKind::Shutdown
+        } else if readings.iter().any(|(_, value, _, limit)| value >= limit) {
+            StateKind::LimitBreach
+        } else if readings.iter().any(|(_, value, warning, _)| value >= warning) {
+            StateKind::Warning
+        } else {
+            StateKind::Normal
+        };
+        let reduces_risk = projected.var <= self.current_var
+            && projected.expected_shortfall.unwrap_or(0.0) <= self.risk_measures.expected_shortfall.unwrap_or(0.0);
+
+        let (verdict, reason) = match self.state.kind() {
+            StateKind::Shutdown => (OrderVerdict::Reject, "trading is shut down".to_string()),
+            StateKind::EngineUnavailable => (OrderVerdict::Reject, "the trading engine is unavailable".to_string()),
+            StateKind::LimitBreach | StateKind::Recovery if !reduces_risk => (
+                OrderVerdict::Reject,
+                format!("only risk reducing orders are accepted in {}", self.state.kind()),
+            ),
+            _ => match projected_state {
+                StateKind::Normal => (OrderVerdict::Approve, "within limits".to_string()),
+                StateKind::Warning | StateKind::Recovery => {
+                    (OrderVerdict::ApproveWithWarning, "projected risk is above the warning level".to_string())
+                }
+                StateKind::LimitBreach | StateKind::Shutdown if reduces_risk => {
+                    (OrderVerdict::ApproveWithWarning, "reduces risk but the book stays above the limit".to_string())
+                }
+                StateKind::LimitBreach | StateKind::Shutdown | StateKind::EngineUnavailable => {
+                    (OrderVerdict::Reject, format!("order would move the book to {}", projected_state))
+                }
+            },
+        };
+        Ok(OrderCheck {
+            verdict,
+            projected_var: projected.var,
+            projected_es: projected.expected_shortfall,
+            projected_state,
+            reason,
+        })
     }
-}
 
-#[derive(Debug)]
-struct NormalOperationState{
-    cmd: TradingEngineCommand
-}
+    pub fn remove_position(&mut self, position_id: &str) {
+        if self.record(BookEvent::PositionRemoved { position_id: position_id.to_string() }) {
+            self.publish(RiskEventKind::PositionRemoved { position_id: position_id.to_string() });
+        }
+        self.update_var();
+        self.check_state();
+        self.send_command();
+    }
+    /// Adds an engine that must obey the risk state, or replaces the one registered as `engine_id`.
+    pub fn register_engine(&mut self, engine_id: &str, sender: impl CommandSink + 'static) {
+        let now = self.now();
+        self.engines.get_mut().insert(engine_id.to_string(), EngineEndpoint::new(Box::new(sender), now));
+        self.publish(RiskEventKind::EngineRegistered { engine_id: engine_id.to_string() });
+    }
 
-impl RiskState for NormalOperationState {
-    fn check_var(&self, context: &RiskManager) -> Option<Box<dyn RiskState>> {
-        if context.current_var >= context.warning_level && context.current_var < context.var_limit {
-            Some(Box::new(WarningLevelState{cmd: TradingEngineCommand::ExecuteTrade}))
-        } else if context.current_var >= context.var_limit {
-            Some(Box::new(LimitBreachState{cmd: TradingEngineCommand::NoTrade}))
-        } else {
-            None
+    /// Listens for what `engine_id` reports back. Without reports its acknowledgements are not
+    /// tracked and the watchdog only notices a closed channel. Returns false if no such engine is registered.
+    pub fn attach_reports(&mut self, engine_id: &str, reports: impl ReportSource + 'static) -> bool {
+        match self.engines.get_mut().get_mut(engine_id) {
+            Some(engine) => {
+                engine.reports = Some(Box::new(reports));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn deregister_engine(&mut self, engine_id: &str) -> bool {
+        let removed = self.engines.get_mut().remove(engine_id).is_some();
+        if removed {
+            self.publish(RiskEventKind::EngineDeregistered { engine_id: engine_id.to_string() });
         }
+        removed
     }
 
-    fn enter_state(&self, _context: &RiskManager) {
-        println!("Entering Normal Operation State");
-        // Reset limitations
+    /// Restricts the liquidation orders sent to `engine_id` to the given positions. Positions it
+    /// reports fills for are added to its scope. Returns false if no such engine is registered.
+    pub fn scope_engine(&mut self, engine_id: &str, position_ids: &[&str]) -> bool {
+        match self.engines.get_mut().get_mut(engine_id) {
+            Some(engine) => {
+                engine.holdings = Some(position_ids.iter().map(|id| id.to_string()).collect());
+                true
+            }
+            None => false,
+        }
     }
 
-    fn exit_state(&self, _context: &RiskManager) {
-        println!("Exiting Normal Operation State");
+    /// Delivery and acknowledgement status of every registered engine.
+    pub fn engine_status(&self) -> Vec<EngineStatus> {
+        self.engines.borrow().iter().map(|(engine_id, engine)| engine.status(engine_id)).collect()
     }
-    fn send_command(&self, context: &RiskManager) {
-        context.trading_engine_sender.send(self.cmd.clone());
+
+    /// Sequence numbers of commands some engine has not acknowledged yet.
+    pub fn unacknowledged(&self) -> Vec<u64> {
+        let pending: BTreeSet<u64> = self
+            .engines
+            .borrow()
+            .values()
+            .flat_map(|engine| engine.pending_acks.keys().copied())
+            .collect();
+        pending.into_iter().collect()
     }
-}
 
-#[derive(Debug)]
-struct WarningLevelState{
-    cmd: TradingEngineCommand
-}
+    /// Applies the acknowledgements and fills the engines have sent back since the last call.
+    pub fn process_engine_reports(&mut self) {
+        let now = self.now();
+        let mut received = Vec::new();
+        let mut lost = Vec::new();
+        for (engine_id, engine) in self.engines.get_mut().iter_mut() {
+            let receiver = match engine.reports.as_mut() {
+                Some(receiver) => receiver,
+                None => continue,
+            };
+            let before = received.len();
+            loop {
+                match receiver.try_report() {
+                    Ok(report) => received.push((engine_id.clone(), report)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        if engine.connected {
+                            engine.connected = false;
+                            lost.push(engine_id.clone());
+                        }
+                        break;
+                    }
+                }
+            }
+            if received.len() > before {
+                engine.last_contact = now;
+            }
+        }
+        for engine_id in lost {
+            let message = format!("Trading engine '{}' closed its report channel.", engine_id);
+            self.notify(Severity::Critical, "Trading engine unavailable", &message);
+        }
 
-impl RiskState for WarningLevelState {
-    fn check_var(&self, context: &RiskManager) -> Option<Box<dyn RiskState>> {
-        if context.current_var < context.warning_level {
-            //context.change_state(Box::new(NormalOperationState));
-            Some(Box::new(NormalOperationState{cmd: TradingEngineCommand::ExecuteTrade}))
-        } else if context.current_var >= context.var_limit {
-            //context.change_state(Box::new(LimitBreachState));
-            Some(Box::new(LimitBreachState{cmd: TradingEngineCommand::NoTrade}))
-        } else {
-            None
+        let mut fills = Vec::new();
+        for (engine_id, report) in received {
+            match report {
+                EngineReport::Heartbeat => (),
+                EngineReport::Ack { sequence } => {
+                    let acked = self
+                        .engines
+                        .get_mut()
+                        .get_mut(&engine_id)
+                        .is_some_and(|engine| engine.pending_acks.remove(&sequence).is_some());
+                    if acked {
+                        self.publish(RiskEventKind::CommandAcknowledged { engine_id, sequence });
+                    }
+                }
+                EngineReport::Fill { position_id, quantity } => {
+                    fills.push((position_id.clone(), quantity));
+                    if let Some(holdings) = self.engines.get_mut().get_mut(&engine_id).and_then(|engine| engine.holdings.as_mut()) {
+                        holdings.insert(position_id.clone());
+                    }
+                    self.publish(RiskEventKind::PositionFilled { engine_id, position_id, quantity });
+                }
+            }
+        }
+        if !fills.is_empty() {
+            self.apply_fills(fills);
         }
     }
 
-    fn enter_state(&self, _context: &RiskManager) {
-        println!("Entering Warning Level State");
-        send_email_notification("Warning Level reached. Increased monitoring and trading limitations are in effect.");
+    /// Books fills reported together and revalues once, as `process_engine_reports` does.
+    pub(crate) fn apply_fills(&mut self, fills: Vec<(String, f64)>) {
+        self.record(BookEvent::Filled { fills });
+        self.update_var();
+        self.check_state();
+        self.send_command();
     }
 
-    fn exit_state(&self, _context: &RiskManager) {
-        println!("Exiting Warning Level State");
+    fn escalate_missing_acks(&mut self) {
+        let now = self.now();
+        let ack_timeout = self.ack_timeout;
+        let mut expired = Vec::new();
+        for (engine_id, engine) in self.engines.get_mut().iter_mut() {
+            let late: Vec<u64> = engine
+                .pending_acks
+                .iter()
+                .filter(|(_, (sent, _))| now.duration_since(*sent).unwrap_or_default() >= ack_timeout)
+                .map(|(sequence, _)| *sequence)
+                .collect();
+            for sequence in late {
+                if let Some((_, command)) = engine.pending_acks.remove(&sequence) {
+                    expired.push((engine_id.clone(), sequence, command));
+                }
+            }
+        }
+        for (engine_id, sequence, command) in expired {
+            let message = format!(
+                "Command #{} ({:?}) was not acknowledged by trading engine '{}' within {:?}.",
+                sequence, command, engine_id, ack_timeout
+            );
+            self.publish(RiskEventKind::AckTimedOut { engine_id, sequence, command });
+            self.notify(Severity::Critical, "Engine not responding", &message);
+        }
     }
-    fn send_command(&self, context: &RiskManager) {
-        context.trading_engine_sender.send(TradingEngineCommand::ExecuteTrade);
+
+    /// Heartbeats are not tracked for acknowledgement: any report from an engine proves it is alive.
+    fn send_heartbeat(&mut self) {
+        let now = self.now();
+        self.last_heartbeat = now;
+        let sequence = self.next_sequence.get();
+        self.next_sequence.set(sequence + 1);
+        let origin = self.state.kind();
+        let mut lost = Vec::new();
+        for (engine_id, engine) in self.engines.get_mut().iter_mut() {
+            let envelope = CommandEnvelope { sequence, timestamp: now, origin, command: TradingEngineCommand::Heartbeat };
+            if engine.sender.deliver(envelope).is_err() && engine.connected {
+                engine.connected = false;
+                lost.push(engine_id.clone());
+            }
+        }
+        for engine_id in lost {
+            let message = format!("Heartbeat failed: trading engine '{}' has stopped.", engine_id);
+            self.notify(Severity::Critical, "Trading engine unavailable", &message);
+        }
     }
-}
 
-#[derive(Debug)]
-struct LimitBreachState{
-    cmd: TradingEngineCommand
-}
+    fn watch_engine(&mut self) {
+        if let Some(interval) = self.heartbeat_interval {
+            if self.now().duration_since(self.last_heartbeat).unwrap_or_default() >= interval {
+                self.send_heartbeat();
+            }
+        }
+        let alive = self.engine_alive();
+        if alive != self.engines_alive {
+            self.engines_alive = alive;
+            self.record(BookEvent::EngineAvailability { alive });
+        }
+        if alive {
+            return;
+        }
+        // The machine decides where a lost engine leads; states without an `EngineLost` rule stay put.
+        let resume = self.state.kind();
+        if let Some(rule) = self.machine.external(resume, &Guard::EngineLost) {
+            self.change_state(self.state_via(rule, Some(resume)));
+            self.send_command();
+        }
+    }
 
-impl RiskState for LimitBreachState {
-    fn check_var(&self, context: &RiskManager) -> Option<Box<dyn RiskState>> {
-        if context.current_var < context.var_limit && context.current_var >= context.warning_level {
-            //context.change_state(Box::new(WarningLevelState));
-            Some(Box::new(WarningLevelState{cmd: TradingEngineCommand::ExecuteTrade}))
-        } else if context.current_var < context.warning_level {
-            //context.change_state(Box::new(NormalOperationState));
-            Some(Box::new(NormalOperationState{cmd: TradingEngineCommand::ExecuteTrade}))
-        } else if context.should_shutdown() {
-            //context.change_state(Box::new(ShutdownState));
-            Some(Box::new(ShutdownState{cmd: TradingEngineCommand::StopEngine}))
-        } else {
-            None
+    /// Re-evaluates the current state without a position change, so time based escalation still happens.
+    /// Also picks up engine reports, escalates missing acknowledgements and runs the engine watchdog.
+    pub fn tick(&mut self) {
+        self.process_engine_reports();
+        self.escalate_missing_acks();
+        self.watch_engine();
+        // Already reported through the notifier.
+        let _ = self.reload_config();
+        let before = self.state.kind();
+        self.check_state();
+        if self.state.kind() != before {
+            self.record(BookEvent::Tick);
+            self.send_command();
+        }
+        self.checkpoint_if_due();
+    }
+
+    pub fn send_command(&self) {
+        self.state.send_command(self);
+    }
+    pub fn check_state(&mut self) {
+        if let Some(state) = self.state.check_var(self) {
+            let (from, to) = (self.state.kind(), state.kind());
+            let dwell = self.policy.min_dwell(from);
+            let elapsed = self.time_in_state();
+            if to < from && elapsed < dwell {
+                self.publish(RiskEventKind::TransitionSuppressed { from, to, remaining: dwell - elapsed });
+            } else {
+                self.change_state(state);
+            }
         }
     }
 
-    fn enter_state(&self, _context: &RiskManager) {
-        println!("Entering Limit Breach State");
-        send_email_notification("Limit Breach! New trades are blocked. Positions may be closed.");
+    pub fn change_state(&mut self, new_state: Box<dyn RiskState>) {
+        let (from, to) = (self.state.kind(), new_state.kind());
+        self.state.exit_state(self);
+        self.publish(RiskEventKind::StateExited { from, to });
+        self.state = new_state;
+        self.state_entered_at = self.now();
+        let (trigger, _, _) = self.trigger();
+        let rule = self.state.entered_by();
+        self.publish(RiskEventKind::StateEntered { from, to, trigger, rule, positions: self.book() });
+        if self.recent_transitions.len() == RECENT_TRANSITIONS {
+            self.recent_transitions.pop_front();
+        }
+        self.recent_transitions.push_back(Transition { at: self.state_entered_at, from, to, trigger, rule });
+        self.state.enter_state(self);
+        self.checkpoint();
     }
 
-    fn exit_state(&self, _context: &RiskManager) {
-        println!("Exiting Limit Breach State");
+    /// Takes the machine's `OperatorReset` transition, by default from `StateKind::Shutdown` to
+    /// reduce-only `StateKind::Recovery`, on behalf of an authenticated operator.
+    pub fn reset_shutdown(&mut self, request: &ResetRequest) -> Result<(), ResetError> {
+        let verdict = if self.machine.external(self.state.kind(), &Guard::OperatorReset).is_none() {
+            Err(ResetError::NoResetTransition(self.state.kind()))
+        } else if !self.authenticator.authenticate(&request.operator_id, &request.credential) {
+            Err(ResetError::Unauthorized(request.operator_id.clone()))
+        } else if request.reason.trim().is_empty() {
+            Err(ResetError::MissingReason)
+        } else if self.at_limit() {
+            let (metric, value, limit) = self.trigger();
+            Err(ResetError::AboveLimit { metric, value, limit })
+        } else {
+            Ok(())
+        };
+        if let Err(err) = &verdict {
+            self.publish(RiskEventKind::ResetRejected {
+                operator_id: request.operator_id.clone(),
+                reason: request.reason.clone(),
+                error: err.to_string(),
+            });
+            return verdict;
+        }
+        self.apply_reset(&request.operator_id, &request.reason);
+        Ok(())
     }
-    fn send_command(&self, context: &RiskManager) {
-        context.trading_engine_sender.send(self.cmd.clone());
+
+    /// The accepted half of `reset_shutdown`, also used by replay, where credentials are not available.
+    pub(crate) fn apply_reset(&mut self, operator_id: &str, reason: &str) {
+        let rule = match self.machine.external(self.state.kind(), &Guard::OperatorReset) {
+            Some(rule) => rule,
+            None => return,
+        };
+        self.record(BookEvent::ShutdownReset { operator_id: operator_id.to_string(), reason: reason.to_string() });
+        self.publish(RiskEventKind::ShutdownReset { operator_id: operator_id.to_string(), reason: reason.to_string() });
+        self.change_state(self.state_via(rule, None));
+        self.send_command();
+    }
+
+    pub fn should_shutdown(&self) -> bool {
+        self.readings().iter().any(|(_, value, _, limit)| *value >= limit * self.shutdown_multiplier)
     }
 }
 
+/// A state as declared in the manager's `StateMachine`: its transitions, entry and exit actions
+/// and standing command all come from there.
 #[derive(Debug)]
-struct ShutdownState{
-    cmd: TradingEngineCommand
+struct DeclaredState{
+    kind: StateKind,
+    // Reached by a Warning timing out: only clears once VaR is back to Normal, not to Warning.
+    escalated: bool,
+    resume: Option<StateKind>,
+    rule: Option<usize>,
 }
 
-impl RiskState for ShutdownState {
-    fn check_var(&self, _context: &RiskManager) -> Option<Box<dyn RiskState>> {
-        // Remain in ShutdownState
-        None
+impl DeclaredState {
+    fn new(kind: StateKind, escalated: bool, resume: Option<StateKind>) -> Self {
+        let resume = match kind {
+            StateKind::EngineUnavailable => Some(resume.unwrap_or(StateKind::Normal)),
+            _ => None,
+        };
+        DeclaredState { kind, escalated: escalated && kind == StateKind::LimitBreach, resume, rule: None }
     }
+}
 
-    fn enter_state(&self, _context: &RiskManager) {
-        println!("Entering Shutdown State");
-        send_email_notification("Shutdown initiated due to extreme risk levels.");
-        self.send_command(_context);
+impl RiskState for DeclaredState {
+    fn kind(&self) -> StateKind {
+        self.kind
     }
 
-    fn exit_state(&self, _context: &RiskManager) {
-        println!("Exiting Shutdown State");
-    }
-    fn send_command(&self, context: &RiskManager) {
-        context.trading_engine_sender.send(self.cmd.clone());
+    fn check_var(&self, context: &RiskManager) -> Option<Box<dyn RiskState>> {
+        let rule = context.machine.next(self, context)?;
+        Some(context.state_via(rule, None))
     }
-}
 
-fn send_email_notification(message: &str) {
-    println!("Sending email notification: {}", message);
-}
+    fn enter_state(&self, context: &RiskManager) {
+        println!("Entering {} State", self.kind);
+        if let Some(spec) = context.machine.spec(self.kind) {
+            for action in &spec.on_entry {
+                action.run(spec, self, context);
+            }
+        }
+        #[cfg(feature = "async")]
+        context.run_entry_hooks();
+    }
 
-pub struct TradingEngine;
-impl TradingEngine {
-    pub fn start(receiver: mpsc::Receiver<TradingEngineCommand>) {
-        thread::spawn(move || {
-            println!("Trading engine started.");
-            loop{
-                let cmd = receiver.recv().unwrap();
-                match cmd {
-                    TradingEngineCommand::ExecuteTrade => {
-                        println!("Executing trade");
-                    }
-                    TradingEngineCommand::StopEngine => {
-                        println!("Stopping trading engine.");
-                        // Perform cleanup if necessary
-                        break;
-                    }
-                    TradingEngineCommand::NoTrade => {
-                        println!("No trade to execute.");
-                    }
-                    // Handle other commands if necessary
-                }
+    fn exit_state(&self, context: &RiskManager) {
+        println!("Exiting {} State", self.kind);
+        if let Some(spec) = context.machine.spec(self.kind) {
+            for action in &spec.on_exit {
+                action.run(spec, self, context);
             }
-            println!("Trading engine stopped.");
-        });
+        }
+    }
+    fn send_command(&self, context: &RiskManager) {
+        if let Some(spec) = context.machine.spec(self.kind) {
+            context.dispatch(spec.command.resolve(context));
+        }
+    }
+    fn escalated(&self) -> bool {
+        self.escalated
+    }
+    fn resumes_to(&self) -> Option<StateKind> {
+        self.resume
+    }
+    fn entered_by(&self) -> Option<usize> {
+        self.rule
     }
 }
 
@@ -238,27 +1170,603 @@ mod tests {
         let var_limit = 100.0;
         let warning_level = 80.0;
         let (trading_engine_sender, trading_engine_receiver) = mpsc::channel();
-        TradingEngine::start(trading_engine_receiver);
+        let (report_sender, _reports) = mpsc::channel();
+        TradingEngine::start(trading_engine_receiver, report_sender);
 
-        let mut risk_manager = RiskManager::new(var_limit, warning_level, trading_engine_sender);
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        let clock = MockClock::default();
+        let recorder = events::EventRecorder::new();
+        let mut risk_manager = RiskManager::new(var_limit, warning_level, trading_engine_sender)
+            .with_clock(Box::new(clock.clone()));
+        risk_manager.subscribe(Box::new(recorder.clone()));
+        clock.advance(Duration::from_secs(1));
         risk_manager.add_position("Position1", 30.0);
         assert_eq!(risk_manager.current_var, 30.0);
+        assert_eq!(risk_manager.time_in_state(), Duration::from_secs(1));
 
         risk_manager.add_position("Position2", 40.0);
         assert_eq!(risk_manager.current_var, 70.0);
 
         risk_manager.add_position("Position3", 20.0);
         assert_eq!(risk_manager.current_var, 90.0);
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(risk_manager.time_in_state(), Duration::from_secs(2));
         risk_manager.add_position("Position4", 15.0);
         assert_eq!(risk_manager.current_var, 105.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
 
         risk_manager.add_position("Position5", 35.0);
         assert_eq!(risk_manager.current_var, 140.0);
         // Simulate the passage of time and check if shutdown is needed
         risk_manager.check_state();
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        clock.advance(Duration::from_secs(2));
         risk_manager.check_state();
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+
+        let entered: Vec<(SystemTime, StateKind)> = recorder.events().into_iter()
+            .filter_map(|event| match event.kind {
+                RiskEventKind::StateEntered { to, .. } => Some((event.timestamp, to)),
+                _ => None,
+            })
+            .collect();
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(entered, vec![
+            (at(1), StateKind::Warning),
+            (at(3), StateKind::LimitBreach),
+            (at(3), StateKind::Shutdown),
+        ]);
+    }
+
+    #[test]
+    fn test_risk_events_published() {
+        let (sender, receiver) = mpsc::channel();
+        let recorder = events::EventRecorder::new();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        risk_manager.subscribe(Box::new(recorder.clone()));
+
+        risk_manager.add_position("Position1", 85.0);
+        let kinds: Vec<RiskEventKind> = recorder.events().into_iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![
+            RiskEventKind::PositionAdded { position_id: "Position1".to_string(), var_contribution: 85.0 },
+            RiskEventKind::VarUpdated { previous_var: 0.0 },
+            RiskEventKind::StateExited { from: StateKind::Normal, to: StateKind::Warning },
+            RiskEventKind::StateEntered {
+                from: StateKind::Normal,
+                to: StateKind::Warning,
+                trigger: RiskMetric::Var,
+                rule: Some(2),
+                positions: BTreeMap::from([("Position1".to_string(), 85.0)]),
+            },
+            RiskEventKind::CommandSent { sequence: 1, command: TradingEngineCommand::Throttle(ThrottleLimits::default()) },
+        ]);
+        assert!(recorder.events().iter().all(|event| event.var_limit == 100.0 && event.warning_level == 80.0));
+        let envelope = receiver.try_recv().unwrap();
+        assert_eq!((envelope.sequence, envelope.origin), (1, StateKind::Warning));
+        assert_eq!(envelope.command, TradingEngineCommand::Throttle(ThrottleLimits::default()));
+
+        recorder.clear();
+        risk_manager.remove_position("Position1");
+        let last = recorder.events().pop().unwrap();
+        assert_eq!(last.state, StateKind::Normal);
+        assert_eq!(last.current_var, 0.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hysteresis_and_min_dwell() {
+        let (sender, _receiver) = mpsc::channel();
+        let recorder = events::EventRecorder::new();
+        let policy = TransitionPolicy::new()
+            .with_hysteresis(5.0, 10.0)
+            .with_min_dwell(StateKind::LimitBreach, Duration::from_secs(3600));
+        // LimitBreach must be left above the warning level.
+        let too_wide = TransitionPolicy::new().with_hysteresis(5.0, 25.0);
+        assert!(matches!(RiskManager::new(100.0, 80.0, sender.clone()).with_policy(too_wide), Err(ConfigError::Invalid(_))));
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender).with_policy(policy).unwrap();
+        risk_manager.subscribe(Box::new(recorder.clone()));
+
+        risk_manager.add_position("Position1", 81.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        // Inside the band: stays in Warning instead of flapping back to Normal.
+        risk_manager.add_position("Position1", 77.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        risk_manager.add_position("Position1", 81.0);
+        risk_manager.add_position("Position1", 74.0);
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+
+        // Escalation ignores the dwell time, de-escalation is held back by it.
+        risk_manager.add_position("Position1", 101.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        risk_manager.add_position("Position1", 50.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        assert!(recorder.events().iter().any(|event| matches!(
+            event.kind,
+            RiskEventKind::TransitionSuppressed { from: StateKind::LimitBreach, to: StateKind::Normal, .. }
+        )));
+    }
+
+    #[test]
+    fn test_reset_from_shutdown() {
+        let (sender, receiver) = mpsc::channel();
+        let recorder = events::EventRecorder::new();
+        let authenticator = recovery::StaticAuthenticator::new().with_operator("ops-1", "s3cret");
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender).with_authenticator(Box::new(authenticator));
+        risk_manager.subscribe(Box::new(recorder.clone()));
+
+        let request = ResetRequest::new("ops-1", "s3cret", "Positions flattened manually");
+        assert_eq!(risk_manager.reset_shutdown(&request), Err(ResetError::NoResetTransition(StateKind::Normal)));
+
+        risk_manager.add_position("Position1", 105.0);
+        risk_manager.add_position("Position2", 20.0);
+        risk_manager.check_state();
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        assert!(matches!(
+            risk_manager.reset_shutdown(&ResetRequest::new("ops-1", "wrong", "retry")),
+            Err(ResetError::Unauthorized(_))
+        ));
+        assert!(matches!(risk_manager.reset_shutdown(&request), Err(ResetError::AboveLimit { metric: RiskMetric::Var, .. })));
+
+        risk_manager.remove_position("Position1");
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        while receiver.try_recv().is_ok() {}
+        risk_manager.reset_shutdown(&request).unwrap();
+        assert_eq!(risk_manager.state(), StateKind::Recovery);
+        assert_eq!(receiver.try_recv().unwrap().command, TradingEngineCommand::ReduceOnly);
+
+        risk_manager.add_position("Position3", 10.0);
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+        assert_eq!(receiver.try_recv().unwrap().command, TradingEngineCommand::Resume);
+        assert_eq!(receiver.try_recv().unwrap().command, TradingEngineCommand::ExecuteTrade);
+
+        let audit: Vec<RiskEventKind> = recorder.events().into_iter()
+            .map(|event| event.kind)
+            .filter(|kind| matches!(kind, RiskEventKind::ShutdownReset { .. } | RiskEventKind::ResetRejected { .. }))
+            .collect();
+        assert_eq!(audit.len(), 4);
+        assert_eq!(audit[3], RiskEventKind::ShutdownReset {
+            operator_id: "ops-1".to_string(),
+            reason: "Positions flattened manually".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_time_based_escalation() {
+        let (sender, receiver) = mpsc::channel();
+        let clock = MockClock::default();
+        let advance = |minutes: u64| clock.advance(Duration::from_secs(minutes * 60));
+        let policy = TransitionPolicy::new()
+            .with_escalation(StateKind::Warning, Duration::from_secs(30 * 60))
+            .with_escalation(StateKind::LimitBreach, Duration::from_secs(15 * 60));
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_policy(policy)
+            .unwrap()
+            .with_clock(Box::new(clock.clone()));
+
+        risk_manager.add_position("Position1", 90.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        advance(29);
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        advance(1);
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+
+        advance(15);
+        while receiver.try_recv().is_ok() {}
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        let commands: Vec<TradingEngineCommand> = receiver.try_iter().map(|envelope| envelope.command).collect();
+        assert_eq!(commands[..2], [TradingEngineCommand::CancelAllOrders, TradingEngineCommand::StopEngine]);
+    }
+
+    #[test]
+    fn test_historical_var_recognises_diversification() {
+        let (sender, _receiver) = mpsc::channel();
+        let equity: Vec<f64> = (0..250).map(|day| if day % 2 == 0 { -1.0 } else { 1.0 }).collect();
+        let hedge: Vec<f64> = equity.iter().map(|pnl| -0.9 * pnl).collect();
+        let engine = var_engine::HistoricalVar::new(0.99, 250).unwrap()
+            .with_pnl("Equity", equity)
+            .with_pnl("Hedge", hedge);
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender).with_var_engine(Box::new(engine));
+
+        risk_manager.add_position("Equity", 90.0);
+        assert_eq!(risk_manager.current_var, 90.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        risk_manager.add_position("Hedge", 90.0);
+        assert!((risk_manager.current_var - 9.0).abs() < 1e-9);
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+
+        // No P&L history for this position: the last computed VaR is kept.
+        risk_manager.add_position("Unknown", 1.0);
+        assert!((risk_manager.current_var - 9.0).abs() < 1e-9);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct RecordingNotifier(std::rc::Rc<std::cell::RefCell<Vec<Alert>>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, alert: &Alert) -> Result<(), notifier::NotifyError> {
+            self.0.borrow_mut().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_expected_shortfall_limits() {
+        let (sender, _receiver) = mpsc::channel();
+        // 95% VaR of 10 but a single 400 loss in the tail: ES is 75.
+        let mut pnl = vec![0.0; 94];
+        pnl.extend([-10.0; 5]);
+        pnl.push(-400.0);
+        let engine = var_engine::HistoricalVar::new(0.95, 100).unwrap().with_pnl("Option", pnl);
+        let alerts = RecordingNotifier::default();
+        let recorder = events::EventRecorder::new();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_var_engine(Box::new(engine))
+            .with_es_limits(50.0, 70.0)
+            .with_notifier(Box::new(alerts.clone()));
+        risk_manager.subscribe(Box::new(recorder.clone()));
+
+        risk_manager.add_position("Option", 1.0);
+        assert_eq!(risk_manager.current_var, 10.0);
+        assert_eq!(risk_manager.risk_measures.expected_shortfall, Some(75.0));
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        assert_eq!(risk_manager.trigger(), (RiskMetric::ExpectedShortfall, 75.0, 70.0));
+
+        let alert = alerts.0.borrow().last().cloned().unwrap();
+        assert_eq!(alert.trigger, RiskMetric::ExpectedShortfall);
+        assert!(alert.message.contains("Top contributors: 1. Option component 10.00 (100.0%)"));
+        assert!(alert.summary().contains("triggered by ES 75.00 against limit 70.00"));
+        assert!(recorder.events().iter().any(|event| event.kind == RiskEventKind::StateEntered {
+            from: StateKind::Normal,
+            to: StateKind::LimitBreach,
+            trigger: RiskMetric::ExpectedShortfall,
+            rule: Some(1),
+            positions: BTreeMap::from([("Option".to_string(), 1.0)]),
+        }));
+
+        risk_manager.add_position("Option", 0.5);
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+    }
+
+    #[test]
+    fn test_check_order_does_not_touch_the_book() {
+        let (sender, receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        risk_manager.add_position("Position1", 60.0);
+        while receiver.try_recv().is_ok() {}
+
+        let check = risk_manager.check_order("Position2", 10.0).unwrap();
+        assert_eq!((check.verdict, check.projected_var, check.projected_state), (OrderVerdict::Approve, 70.0, StateKind::Normal));
+        let check = risk_manager.check_order("Position2", 30.0).unwrap();
+        assert_eq!((check.verdict, check.projected_state), (OrderVerdict::ApproveWithWarning, StateKind::Warning));
+        let check = risk_manager.check_order("Position2", 45.0).unwrap();
+        assert_eq!((check.verdict, check.projected_var), (OrderVerdict::Reject, 105.0));
+        assert!(!check.is_approved());
+
+        assert_eq!(risk_manager.current_var, 60.0);
+        assert!(!risk_manager.positions.contains_key("Position2"));
+        assert!(receiver.try_recv().is_err());
+
+        // In breach only orders that bring risk down get through.
+        risk_manager.add_position("Position2", 50.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        assert_eq!(risk_manager.check_order("Position3", 1.0).unwrap().verdict, OrderVerdict::Reject);
+        let check = risk_manager.check_order("Position2", 10.0).unwrap();
+        assert_eq!((check.verdict, check.projected_state), (OrderVerdict::Approve, StateKind::Normal));
+    }
+
+    #[test]
+    fn test_limit_breach_sends_liquidation_plan() {
+        let (sender, receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_liquidation_strategy(Box::new(liquidation::OptimisedLiquidation::default()));
+        risk_manager.add_position("Position1", 60.0);
+        risk_manager.add_position("Position2", 30.0);
+        while receiver.try_recv().is_ok() {}
+
+        risk_manager.add_position("Position3", 20.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        assert_eq!(receiver.try_recv().unwrap().command, TradingEngineCommand::CancelAllOrders);
+        let orders = match receiver.try_recv().unwrap().command {
+            TradingEngineCommand::Liquidate(orders) => orders,
+            other => panic!("expected a liquidation plan, got {:?}", other),
+        };
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].position_id, "Position1");
+        assert!((orders[0].quantity - 30.0).abs() < 1e-6);
+        assert_eq!(receiver.try_recv().unwrap().command, TradingEngineCommand::NoTrade);
+    }
+
+    #[test]
+    fn test_engine_fills_and_acknowledgements() {
+        let (sender, receiver) = mpsc::channel();
+        let (report_sender, reports) = mpsc::channel();
+        TradingEngine::start(receiver, report_sender);
+        let recorder = events::EventRecorder::new();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_engine_reports(reports, Duration::from_secs(5));
+        risk_manager.subscribe(Box::new(recorder.clone()));
+
+        risk_manager.add_position("Position1", 60.0);
+        risk_manager.add_position("Position2", 30.0);
+        // Breach: the engine executes the liquidation plan and reports the fill back.
+        risk_manager.add_position("Position3", 20.0);
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while risk_manager.state() != StateKind::Normal || !risk_manager.unacknowledged().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "the engine did not settle the breach in time");
+            risk_manager.tick();
+            std::thread::yield_now();
+        }
+        assert!(risk_manager.current_var < 80.0);
+        assert!((risk_manager.positions["Position1"] - 30.0).abs() < 1e-6);
+        assert!(recorder.events().iter().any(|event| matches!(
+            event.kind,
+            RiskEventKind::PositionFilled { ref position_id, .. } if position_id == "Position1"
+        )));
+    }
+
+    #[test]
+    fn test_missing_acknowledgement_is_escalated() {
+        let (sender, _receiver) = mpsc::channel();
+        let (_report_sender, reports) = mpsc::channel();
+        let clock = MockClock::default();
+        let alerts = RecordingNotifier::default();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_notifier(Box::new(alerts.clone()))
+            .with_engine_reports(reports, Duration::from_secs(5));
+
+        risk_manager.add_position("Position1", 10.0);
+        assert_eq!(risk_manager.unacknowledged(), vec![1]);
+        clock.advance(Duration::from_secs(4));
+        risk_manager.tick();
+        assert!(alerts.0.borrow().is_empty());
+        clock.advance(Duration::from_secs(1));
+        risk_manager.tick();
+        assert!(risk_manager.unacknowledged().is_empty());
+        let alert = alerts.0.borrow().last().cloned().unwrap();
+        assert_eq!((alert.severity, alert.subject.as_str()), (Severity::Critical, "Engine not responding"));
+    }
+
+    #[test]
+    fn test_watchdog_detects_lost_engine() {
+        let (sender, receiver) = mpsc::channel();
+        let (report_sender, reports) = mpsc::channel();
+        let clock = MockClock::default();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_engine_reports(reports, Duration::from_secs(10))
+            .with_watchdog(Duration::from_secs(1), Duration::from_secs(3));
+
+        risk_manager.add_position("Position1", 10.0);
+        clock.advance(Duration::from_secs(1));
+        risk_manager.tick();
+        let commands: Vec<TradingEngineCommand> = receiver.try_iter().map(|envelope| envelope.command).collect();
+        assert_eq!(commands.last(), Some(&TradingEngineCommand::Heartbeat));
+
+        clock.advance(Duration::from_secs(2));
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::EngineUnavailable);
+        assert!(!risk_manager.check_order("Position2", 1.0).unwrap().is_approved());
+
+        report_sender.send(EngineReport::Heartbeat).unwrap();
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+
+        // A closed command channel is noticed on the next send, and there is no coming back from it.
+        drop(receiver);
+        risk_manager.add_position("Position2", 5.0);
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::EngineUnavailable);
+        report_sender.send(EngineReport::Heartbeat).unwrap();
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::EngineUnavailable);
+    }
+
+    #[test]
+    fn test_watchdog_and_reset_follow_the_machine() {
+        // The standard machine, but Normal ignores a lost engine and LimitBreach can be reset.
+        let standard = StateMachine::default();
+        let mut builder = StateMachine::builder();
+        for spec in standard.states() {
+            builder = builder.state(spec.clone());
+        }
+        for rule in standard.transitions() {
+            if rule.from == StateKind::Normal && rule.guard == Guard::EngineLost {
+                continue;
+            }
+            builder = match rule.escalates {
+                true => builder.escalation(rule.from, rule.to, rule.guard.clone()),
+                false => builder.transition(rule.from, rule.to, rule.guard.clone()),
+            };
+        }
+        let machine = builder.transition(StateKind::LimitBreach, StateKind::Recovery, Guard::OperatorReset).build().unwrap();
+
+        let (sender, _receiver) = mpsc::channel();
+        let (_report_sender, reports) = mpsc::channel();
+        let clock = MockClock::default();
+        let authenticator = recovery::StaticAuthenticator::new().with_operator("ops-1", "s3cret");
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_state_machine(machine)
+            .with_policy(TransitionPolicy::new().with_min_dwell(StateKind::LimitBreach, Duration::from_secs(60)))
+            .unwrap()
+            .with_authenticator(Box::new(authenticator))
+            .with_engine_reports(reports, Duration::from_secs(10))
+            .with_watchdog(Duration::from_secs(1), Duration::from_secs(3));
+
+        clock.advance(Duration::from_secs(5));
+        risk_manager.tick();
+        assert!(!risk_manager.engine_alive());
+        assert_eq!(risk_manager.state(), StateKind::Normal);
+
+        risk_manager.add_position("Position1", 105.0);
+        risk_manager.add_position("Position1", 90.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        risk_manager.reset_shutdown(&ResetRequest::new("ops-1", "s3cret", "Desk flattened")).unwrap();
+        assert_eq!(risk_manager.state(), StateKind::Recovery);
+        // Recovery declares EngineLost, so the still-silent engine is picked up on the next tick.
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::EngineUnavailable);
+        assert_eq!(
+            risk_manager.reset_shutdown(&ResetRequest::new("ops-1", "s3cret", "again")),
+            Err(ResetError::NoResetTransition(StateKind::EngineUnavailable))
+        );
+    }
+
+    #[test]
+    fn test_commands_broadcast_to_every_engine() {
+        let (sender_a, receiver_a) = mpsc::channel();
+        let (_report_sender_a, reports_a) = mpsc::channel();
+        let (sender_b, receiver_b) = mpsc::channel();
+        let (report_sender_b, reports_b) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender_a)
+            .with_engine_reports(reports_a, Duration::from_secs(5));
+        risk_manager.register_engine("venue-b", sender_b);
+        assert!(risk_manager.attach_reports("venue-b", reports_b));
+        assert!(risk_manager.scope_engine(DEFAULT_ENGINE, &["Position1"]));
+        assert!(risk_manager.scope_engine("venue-b", &["Position2"]));
+
+        risk_manager.add_position("Position1", 60.0);
+        risk_manager.add_position("Position2", 50.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        let commands_a: Vec<CommandEnvelope> = receiver_a.try_iter().collect();
+        let commands_b: Vec<CommandEnvelope> = receiver_b.try_iter().collect();
+        assert!(commands_b.iter().any(|envelope| envelope.command == TradingEngineCommand::CancelAllOrders));
+        // Only the engine holding the position being cut is asked to liquidate.
+        assert!(commands_a.iter().any(|envelope| matches!(envelope.command, TradingEngineCommand::Liquidate(_))));
+        assert!(!commands_b.iter().any(|envelope| matches!(envelope.command, TradingEngineCommand::Liquidate(_))));
+        assert_eq!(commands_a[0].sequence, commands_b[0].sequence);
+
+        for envelope in &commands_b {
+            report_sender_b.send(EngineReport::Ack { sequence: envelope.sequence }).unwrap();
+        }
+        risk_manager.tick();
+        let status = risk_manager.engine_status();
+        assert_eq!(status.len(), 2);
+        assert_eq!((status[0].engine_id.as_str(), status[0].delivered), (DEFAULT_ENGINE, commands_a.len() as u64));
+        assert_eq!(status[0].unacknowledged.len(), commands_a.len());
+        assert_eq!((status[1].engine_id.as_str(), status[1].unacknowledged.len()), ("venue-b", 0));
+
+        assert!(risk_manager.deregister_engine("venue-b"));
+        assert!(!risk_manager.deregister_engine("venue-b"));
+        assert_eq!(risk_manager.engine_status().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_resumes_without_entry_side_effects() {
+        let path = std::env::temp_dir().join(format!("risk_checkpoint_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (sender, _receiver) = mpsc::channel();
+        let clock = MockClock::default();
+        let policy = TransitionPolicy::new().with_escalation(StateKind::LimitBreach, Duration::from_secs(15 * 60));
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_policy(policy.clone().with_hysteresis(5.0, 10.0))
+            .unwrap()
+            .with_checkpoints(&path, Duration::from_secs(60));
+        risk_manager.add_position("Position1", 60.0);
+        risk_manager.add_position("Position2", 45.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        // The transition itself was checkpointed; a later tick refreshes the snapshot once due.
+        assert_eq!(snapshot::RiskSnapshot::load(&path).unwrap().state, StateKind::LimitBreach);
+        clock.advance(Duration::from_secs(10 * 60));
+        risk_manager.tick();
+        let snapshot = snapshot::RiskSnapshot::load(&path).unwrap();
+        assert_eq!(snapshot, risk_manager.snapshot());
+        drop(risk_manager);
+        // A snapshot whose limits fail validation is rejected rather than taken over.
+        let invalid = snapshot::RiskSnapshot { limit_band: 100.0, ..snapshot.clone() };
+        let rejected = RiskManager::new(100.0, 80.0, mpsc::channel().0).restore(&invalid);
+        assert!(matches!(rejected, Err(ConfigError::Invalid(_))));
+
+        let (sender, receiver) = mpsc::channel();
+        let alerts = RecordingNotifier::default();
+        let recorder = events::EventRecorder::new();
+        let mut restored = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_policy(policy)
+            .unwrap()
+            .with_notifier(Box::new(alerts.clone()))
+            .restore(&snapshot)
+            .unwrap();
+        restored.subscribe(Box::new(recorder.clone()));
+        assert_eq!(restored.state(), StateKind::LimitBreach);
+        assert_eq!(restored.current_var, 105.0);
+        assert_eq!(restored.time_in_state(), Duration::from_secs(10 * 60));
+        assert_eq!((restored.policy.warning_band, restored.policy.limit_band), (5.0, 10.0));
+        assert!(alerts.0.borrow().is_empty());
+        let commands: Vec<CommandEnvelope> = receiver.try_iter().collect();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].command, TradingEngineCommand::NoTrade);
+        assert_eq!(commands[0].sequence, snapshot.next_sequence);
+
+        // The escalation timer carries on from the original entry into LimitBreach.
+        clock.advance(Duration::from_secs(5 * 60));
+        restored.tick();
+        assert_eq!(restored.state(), StateKind::Shutdown);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_config_hot_reload() {
+        let path = std::env::temp_dir().join(format!("risk_config_{}.toml", std::process::id()));
+        let write = |text: &str, modified: u64| {
+            std::fs::write(&path, text).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified)).unwrap();
+        };
+        let alert_path = std::env::temp_dir().join(format!("risk_config_alerts_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&alert_path);
+        let routes = format!("\n[[notifier]]\ntype = \"file\"\npath = \"{}\"\n", alert_path.display());
+        write(&format!("[limits]\nvar_limit = 100\nwarning_ratio = 0.8\nshutdown_multiplier = 1.5\n{}", routes), 1);
+        let (sender, receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_notifier(Box::new(RecordingNotifier::default()))
+            .with_config_file(&path)
+            .unwrap();
+        risk_manager.add_position("Position1", 130.0);
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+
+        write(&format!("[limits]\nvar_limit = 100\nwarning_ratio = 0.8\n\n[hysteresis]\nlimit_band = 10\n{}", routes), 2);
+        while receiver.try_recv().is_ok() {}
+        risk_manager.tick();
+        assert_eq!(risk_manager.shutdown_multiplier, 1.2);
+        assert_eq!(risk_manager.limit_exit_level(), 90.0);
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        assert!(receiver.try_iter().any(|envelope| envelope.command == TradingEngineCommand::StopEngine));
+
+        write("[limits]\nvar_limit = 100\nwarning_ratio = 0.8\nshutdown_multiplier = 0.9\n", 3);
+        assert!(matches!(risk_manager.reload_config(), Err(ConfigError::Invalid(_))));
+        assert_eq!(risk_manager.shutdown_multiplier, 1.2);
+        let alerts = std::fs::read_to_string(&alert_path).unwrap();
+        assert!(alerts.lines().last().unwrap().contains("[WARNING] Config reload failed"));
+        assert!(matches!(risk_manager.reload_config(), Ok(false)));
+
+        // Deleting the routes from the file sends alerts back to the console.
+        write("[limits]\nvar_limit = 100\nwarning_ratio = 0.8\n", 4);
+        assert!(matches!(risk_manager.reload_config(), Ok(true)));
+        assert_eq!(format!("{:?}", risk_manager.notifier), "ConsoleNotifier");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&alert_path).unwrap();
+    }
+
+    #[test]
+    fn test_any_state_jumps_to_shutdown() {
+        let (sender, receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        risk_manager.add_position("Position1", 200.0);
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        let commands: Vec<TradingEngineCommand> = receiver.try_iter().map(|envelope| envelope.command).collect();
+        assert!(commands.contains(&TradingEngineCommand::StopEngine));
+        assert!(!commands.contains(&TradingEngineCommand::NoTrade));
+
+        let (sender, _receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        risk_manager.add_position("Position1", 85.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        risk_manager.add_position("Position2", 40.0);
+        assert_eq!(risk_manager.state(), StateKind::Shutdown);
+        assert!(risk_manager.state_machine().validate().is_empty());
+    }
+}
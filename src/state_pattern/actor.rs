@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::pre_trade::OrderCheck;
+use super::recovery::{ResetError, ResetRequest};
+use super::var_engine::{RiskMeasures, VarError};
+use super::{RiskManager, StateKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorError {
+    /// The queue is at capacity; only returned by the `try_` methods.
+    QueueFull,
+    /// The actor thread has stopped.
+    Stopped,
+    /// `spawn` was asked for a queue that can never hold a request.
+    ZeroCapacity,
+    Var(VarError),
+    Reset(ResetError),
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActorError::QueueFull => write!(f, "risk manager queue is full"),
+            ActorError::Stopped => write!(f, "risk manager has stopped"),
+            ActorError::ZeroCapacity => write!(f, "risk manager queue capacity must be at least one"),
+            ActorError::Var(err) => write!(f, "{}", err),
+            ActorError::Reset(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ActorError {}
+
+/// Read-only copy of the manager's book and state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskView {
+    pub state: StateKind,
+    pub current_var: f64,
+    pub risk_measures: RiskMeasures,
+    pub positions: HashMap<String, f64>,
+}
+
+/// Backpressure figures for the actor's request queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueStats {
+    pub capacity: usize,
+    /// Requests waiting to be handled right now.
+    pub depth: usize,
+    pub max_depth: usize,
+    pub enqueued: u64,
+    /// Sends that found the queue full and had to wait.
+    pub blocked: u64,
+    /// `try_` sends turned away because the queue was full.
+    pub rejected: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct QueueMetrics {
+    pub(crate) capacity: usize,
+    pub(crate) depth: AtomicUsize,
+    pub(crate) max_depth: AtomicUsize,
+    pub(crate) enqueued: AtomicU64,
+    pub(crate) blocked: AtomicU64,
+    pub(crate) rejected: AtomicU64,
+}
+
+impl QueueMetrics {
+    pub(crate) fn new(capacity: usize) -> Self {
+        QueueMetrics { capacity, ..QueueMetrics::default() }
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        QueueStats {
+            capacity: self.capacity,
+            depth: self.depth.load(Ordering::SeqCst),
+            max_depth: self.max_depth.load(Ordering::SeqCst),
+            enqueued: self.enqueued.load(Ordering::SeqCst),
+            blocked: self.blocked.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Senders count a request before sending it, so the actor never sees one the depth does not include yet.
+    pub(crate) fn settle(&self, sent: Result<(), ActorError>) -> Result<(), ActorError> {
+        match sent {
+            Ok(()) => {
+                self.enqueued.fetch_add(1, Ordering::SeqCst);
+                let depth = self.depth.load(Ordering::SeqCst);
+                self.max_depth.fetch_max(depth.min(self.capacity), Ordering::SeqCst);
+            }
+            Err(_) => {
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        sent
+    }
+}
+
+pub(crate) enum Reply<T> {
+    Blocking(Sender<T>),
+    #[cfg(feature = "async")]
+    Async(tokio::sync::oneshot::Sender<T>),
+}
+
+impl<T> Reply<T> {
+    // A caller that gave up waiting for its reply is not an error for the actor.
+    fn send(self, value: T) {
+        match self {
+            Reply::Blocking(reply) => {
+                let _ = reply.send(value);
+            }
+            #[cfg(feature = "async")]
+            Reply::Async(reply) => {
+                let _ = reply.send(value);
+            }
+        }
+    }
+}
+
+pub(crate) enum Request {
+    AddPosition { position_id: String, size: f64 },
+    RemovePosition { position_id: String },
+    Tick,
+    View(Reply<RiskView>),
+    CheckOrder { position_id: String, size: f64, reply: Reply<Result<OrderCheck, VarError>> },
+    ResetShutdown { request: ResetRequest, reply: Reply<Result<(), ResetError>> },
+    Stop,
+}
+
+impl Request {
+    /// Handles the request; false once the actor should stop.
+    pub(crate) fn apply(self, manager: &mut RiskManager) -> bool {
+        match self {
+            Request::AddPosition { position_id, size } => manager.add_position(&position_id, size),
+            Request::RemovePosition { position_id } => manager.remove_position(&position_id),
+            Request::Tick => manager.tick(),
+            Request::View(reply) => reply.send(RiskView {
+                state: manager.state(),
+                current_var: manager.current_var,
+                risk_measures: manager.risk_measures,
+                positions: manager.positions.clone(),
+            }),
+            Request::CheckOrder { position_id, size, reply } => reply.send(manager.check_order(&position_id, size)),
+            Request::ResetShutdown { request, reply } => reply.send(manager.reset_shutdown(&request)),
+            Request::Stop => return false,
+        }
+        true
+    }
+}
+
+/// Runs a `RiskManager` on a dedicated thread and serialises every update and query to it.
+///
+/// `RiskManager` is not `Send` (its notifiers and subscribers need not be), so the actor
+/// builds it on its own thread from `factory`. Requests go through a bounded queue of
+/// `capacity`, at least one; when `tick_interval` is set the actor also ticks the manager on that cadence.
+pub struct RiskActor;
+
+impl RiskActor {
+    pub fn spawn<F>(
+        capacity: usize,
+        tick_interval: Option<Duration>,
+        factory: F,
+    ) -> Result<(RiskHandle, JoinHandle<()>), ActorError>
+    where
+        F: FnOnce() -> RiskManager + Send + 'static,
+    {
+        if capacity == 0 {
+            return Err(ActorError::ZeroCapacity);
+        }
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let metrics = Arc::new(QueueMetrics::new(capacity));
+        let handle = RiskHandle { sender, metrics: Arc::clone(&metrics) };
+        let thread = thread::spawn(move || {
+            let mut manager = factory();
+            let mut next_tick = tick_interval.map(|interval| Instant::now() + interval);
+            loop {
+                let request = match next_tick {
+                    Some(due) => match receiver.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                        Ok(request) => Some(request),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    None => match receiver.recv() {
+                        Ok(request) => Some(request),
+                        Err(_) => break,
+                    },
+                };
+                if let Some(request) = request {
+                    metrics.depth.fetch_sub(1, Ordering::SeqCst);
+                    if !request.apply(&mut manager) {
+                        break;
+                    }
+                }
+                if let (Some(due), Some(interval)) = (next_tick, tick_interval) {
+                    if Instant::now() >= due {
+                        manager.tick();
+                        next_tick = Some(Instant::now() + interval);
+                    }
+                }
+            }
+        });
+        Ok((handle, thread))
+    }
+}
+
+/// Cheap to clone and `Send`: every feeder gets its own copy.
+///
+/// The plain methods wait for room in the queue; the `try_` variants return `QueueFull` instead.
+#[derive(Debug, Clone)]
+pub struct RiskHandle {
+    sender: SyncSender<Request>,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl RiskHandle {
+    pub fn add_position(&self, position_id: &str, size: f64) -> Result<(), ActorError> {
+        self.send(Request::AddPosition { position_id: position_id.to_string(), size })
+    }
+
+    pub fn try_add_position(&self, position_id: &str, size: f64) -> Result<(), ActorError> {
+        self.try_send(Request::AddPosition { position_id: position_id.to_string(), size })
+    }
+
+    pub fn remove_position(&self, position_id: &str) -> Result<(), ActorError> {
+        self.send(Request::RemovePosition { position_id: position_id.to_string() })
+    }
+
+    pub fn try_remove_position(&self, position_id: &str) -> Result<(), ActorError> {
+        self.try_send(Request::RemovePosition { position_id: position_id.to_string() })
+    }
+
+    pub fn tick(&self) -> Result<(), ActorError> {
+        self.send(Request::Tick)
+    }
+
+    /// Waits until every request queued before it has been handled.
+    pub fn view(&self) -> Result<RiskView, ActorError> {
+        let (reply, response) = mpsc::channel();
+        self.send(Request::View(Reply::Blocking(reply)))?;
+        response.recv().map_err(|_| ActorError::Stopped)
+    }
+
+    pub fn state(&self) -> Result<StateKind, ActorError> {
+        Ok(self.view()?.state)
+    }
+
+    pub fn check_order(&self, position_id: &str, size: f64) -> Result<OrderCheck, ActorError> {
+        let (reply, response) = mpsc::channel();
+        self.send(Request::CheckOrder { position_id: position_id.to_string(), size, reply: Reply::Blocking(reply) })?;
+        response.recv().map_err(|_| ActorError::Stopped)?.map_err(ActorError::Var)
+    }
+
+    /// Leaves Shutdown on behalf of an operator, in turn with the other requests.
+    pub fn reset_shutdown(&self, request: &ResetRequest) -> Result<(), ActorError> {
+        let (reply, response) = mpsc::channel();
+        self.send(Request::ResetShutdown { request: request.clone(), reply: Reply::Blocking(reply) })?;
+        response.recv().map_err(|_| ActorError::Stopped)?.map_err(ActorError::Reset)
+    }
+
+    /// Asks the actor to stop once the requests already queued have been handled.
+    pub fn stop(&self) -> Result<(), ActorError> {
+        self.send(Request::Stop)
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.metrics.stats()
+    }
+
+    fn send(&self, request: Request) -> Result<(), ActorError> {
+        self.metrics.depth.fetch_add(1, Ordering::SeqCst);
+        let sent = match self.sender.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(request)) => {
+                self.metrics.blocked.fetch_add(1, Ordering::SeqCst);
+                self.sender.send(request).map_err(|_| ActorError::Stopped)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(ActorError::Stopped),
+        };
+        self.metrics.settle(sent)
+    }
+
+    fn try_send(&self, request: Request) -> Result<(), ActorError> {
+        self.metrics.depth.fetch_add(1, Ordering::SeqCst);
+        let sent = match self.sender.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.metrics.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(ActorError::QueueFull)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(ActorError::Stopped),
+        };
+        self.metrics.settle(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::recovery::StaticAuthenticator;
+
+    #[test]
+    fn test_concurrent_feeders_and_backpressure() {
+        let (engine_sender, _engine_receiver) = mpsc::channel();
+        let (gate, gate_receiver) = mpsc::channel::<()>();
+        // The actor only starts reading once the gate opens, so the queue can be filled up first.
+        let (handle, thread) = RiskActor::spawn(2, None, move || {
+            gate_receiver.recv().unwrap();
+            RiskManager::new(100.0, 80.0, engine_sender)
+        })
+        .unwrap();
+        handle.try_add_position("Position1", 10.0).unwrap();
+        handle.try_add_position("Position2", 10.0).unwrap();
+        assert_eq!(handle.try_add_position("Position3", 10.0), Err(ActorError::QueueFull));
+        let stats = handle.stats();
+        assert_eq!((stats.capacity, stats.depth, stats.rejected), (2, 2, 1));
+        gate.send(()).unwrap();
+
+        let feeders: Vec<_> = (0..4)
+            .map(|feeder| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for i in 0..5 {
+                        handle.add_position(&format!("Feed{}-{}", feeder, i), 3.0).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for feeder in feeders {
+            feeder.join().unwrap();
+        }
+        let view = handle.view().unwrap();
+        assert_eq!(view.positions.len(), 22);
+        assert_eq!(view.current_var, 80.0);
+        assert_eq!(view.state, StateKind::Warning);
+        assert!(!handle.check_order("Position3", 30.0).unwrap().is_approved());
+
+        handle.stop().unwrap();
+        thread.join().unwrap();
+        assert_eq!(handle.add_position("Position3", 1.0), Err(ActorError::Stopped));
+        assert_eq!(handle.stats().enqueued, 2 + 20 + 1 + 1 + 1);
+    }
+
+    #[test]
+    fn test_reset_shutdown_through_handle() {
+        let (engine_sender, _engine_receiver) = mpsc::channel();
+        assert!(matches!(
+            RiskActor::spawn(0, None, || RiskManager::new(100.0, 80.0, mpsc::channel().0)),
+            Err(ActorError::ZeroCapacity)
+        ));
+        let (handle, thread) = RiskActor::spawn(4, None, move || {
+            RiskManager::new(100.0, 80.0, engine_sender)
+                .with_authenticator(Box::new(StaticAuthenticator::new().with_operator("ops-1", "s3cret")))
+        })
+        .unwrap();
+        let request = ResetRequest::new("ops-1", "s3cret", "Book flattened");
+        assert_eq!(handle.reset_shutdown(&request), Err(ActorError::Reset(ResetError::NoResetTransition(StateKind::Normal))));
+
+        handle.add_position("Position1", 130.0).unwrap();
+        assert_eq!(handle.state(), Ok(StateKind::Shutdown));
+        handle.remove_position("Position1").unwrap();
+        assert!(matches!(
+            handle.reset_shutdown(&ResetRequest::new("ops-1", "wrong", "retry")),
+            Err(ActorError::Reset(ResetError::Unauthorized(_)))
+        ));
+        handle.reset_shutdown(&request).unwrap();
+        assert_eq!(handle.state(), Ok(StateKind::Recovery));
+
+        handle.stop().unwrap();
+        thread.join().unwrap();
+    }
+}
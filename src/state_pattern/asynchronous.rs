@@ -0,0 +1,415 @@
+//! Tokio flavoured counterparts of the engine channels, notifiers, state hooks and actor.
+//!
+//! `RiskManager` itself stays synchronous: slow work such as webhooks is moved onto tasks
+//! so that position updates never wait on it. Everything that spawns a task must be
+//! called from within a Tokio runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::thread;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, error::TrySendError, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
+
+use super::actor::{ActorError, QueueMetrics, QueueStats, Reply, Request, RiskActor, RiskView};
+use super::engine::{execute, CommandSink, EngineReport, ReportSource, TradingEngine};
+use super::events::{RiskEvent, RiskEventKind};
+use super::notifier::{Alert, Notifier, NotifyError, WebhookNotifier};
+use super::pre_trade::OrderCheck;
+use super::recovery::ResetRequest;
+use super::{CommandEnvelope, RiskManager, StateKind};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+impl CommandSink for UnboundedSender<CommandEnvelope> {
+    fn deliver(&self, envelope: CommandEnvelope) -> Result<(), CommandEnvelope> {
+        self.send(envelope).map_err(|err| err.0)
+    }
+}
+
+impl ReportSource for UnboundedReceiver<EngineReport> {
+    fn try_report(&mut self) -> Result<EngineReport, TryRecvError> {
+        self.try_recv().map_err(|err| match err {
+            mpsc::error::TryRecvError::Empty => TryRecvError::Empty,
+            mpsc::error::TryRecvError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+}
+
+impl TradingEngine {
+    /// Same behaviour as `spawn`, as a task fed by Tokio channels.
+    pub fn spawn_async(
+        self,
+        mut commands: UnboundedReceiver<CommandEnvelope>,
+        reports: UnboundedSender<EngineReport>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            println!("Trading engine started.");
+            let mut last_heard = Instant::now();
+            let mut last_heartbeat = Instant::now();
+            let mut halted = false;
+            loop {
+                match timeout(self.heartbeat_interval, commands.recv()).await {
+                    Ok(Some(envelope)) => {
+                        last_heard = Instant::now();
+                        if halted {
+                            println!("Risk manager is back, trading may continue.");
+                            halted = false;
+                        }
+                        for report in execute(envelope) {
+                            let _ = reports.send(report);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => (),
+                }
+                if !halted && last_heard.elapsed() >= self.manager_timeout {
+                    println!("No heartbeat from the risk manager, halting trading.");
+                    halted = true;
+                }
+                if last_heartbeat.elapsed() >= self.heartbeat_interval {
+                    last_heartbeat = Instant::now();
+                    let _ = reports.send(EngineReport::Heartbeat);
+                }
+            }
+            println!("Trading engine stopped.");
+        })
+    }
+}
+
+pub trait AsyncNotifier: Send + Sync {
+    fn deliver<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), NotifyError>>;
+}
+
+impl AsyncNotifier for WebhookNotifier {
+    fn deliver<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(async move {
+            let (authority, request) = self.request(alert)?;
+            let post = async {
+                let mut stream = TcpStream::connect(&authority).await?;
+                stream.write_all(request.as_bytes()).await?;
+                let mut status_line = String::new();
+                BufReader::new(stream).read_line(&mut status_line).await?;
+                Ok::<_, NotifyError>(status_line)
+            };
+            let status_line = timeout(self.timeout, post)
+                .await
+                .map_err(|_| NotifyError::Protocol(format!("webhook timed out after {:?}", self.timeout)))??;
+            Self::check_status(&status_line)
+        })
+    }
+}
+
+/// Runs a blocking `Notifier`, e.g. `SmtpNotifier`, on Tokio's blocking thread pool.
+#[derive(Debug)]
+pub struct BlockingNotifier<N>(pub Arc<N>);
+
+impl<N: Notifier + Send + Sync + 'static> AsyncNotifier for BlockingNotifier<N> {
+    fn deliver<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), NotifyError>> {
+        let notifier = Arc::clone(&self.0);
+        let alert = alert.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || notifier.notify(&alert))
+                .await
+                .map_err(|err| NotifyError::Protocol(format!("notifier task failed: {}", err)))?
+        })
+    }
+}
+
+/// A `Notifier` for `RiskManager` that only queues the alert: a task delivers it through the
+/// wrapped `AsyncNotifier`, in order, and logs failures the way `RiskManager::notify` does.
+#[derive(Debug)]
+pub struct BackgroundNotifier {
+    sender: UnboundedSender<Alert>,
+}
+
+impl BackgroundNotifier {
+    pub fn spawn(notifier: impl AsyncNotifier + 'static) -> (Self, JoinHandle<()>) {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Alert>();
+        let task = tokio::spawn(async move {
+            while let Some(alert) = receiver.recv().await {
+                if let Err(err) = notifier.deliver(&alert).await {
+                    eprintln!("Failed to deliver risk alert '{}': {}", alert.subject, err);
+                }
+            }
+        });
+        (BackgroundNotifier { sender }, task)
+    }
+}
+
+impl Notifier for BackgroundNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        self.sender
+            .send(alert.clone())
+            .map_err(|_| NotifyError::Protocol("background notifier has stopped".to_string()))
+    }
+}
+
+/// Work to do when a state is entered, run from its `enter_state` without holding it up.
+pub trait AsyncStateHook: Send + Sync {
+    fn on_enter<'a>(&'a self, event: &'a RiskEvent) -> BoxFuture<'a, ()>;
+}
+
+impl<F, Fut> AsyncStateHook for F
+where
+    F: Fn(RiskEvent) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn on_enter<'a>(&'a self, event: &'a RiskEvent) -> BoxFuture<'a, ()> {
+        Box::pin(self(event.clone()))
+    }
+}
+
+/// Hooks per state, run one after another on a task, in the order the states were entered.
+#[derive(Default)]
+pub struct AsyncStateHooks {
+    hooks: Vec<(StateKind, Box<dyn AsyncStateHook>)>,
+}
+
+impl AsyncStateHooks {
+    pub fn new() -> Self {
+        AsyncStateHooks::default()
+    }
+
+    pub fn on_enter(mut self, state: StateKind, hook: impl AsyncStateHook + 'static) -> Self {
+        self.hooks.push((state, Box::new(hook)));
+        self
+    }
+
+    /// Starts the task; hand the returned `EntryHooks` to `RiskManager::with_async_hooks`.
+    pub fn spawn(self) -> (EntryHooks, JoinHandle<()>) {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<RiskEvent>();
+        let task = tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                for (state, hook) in &self.hooks {
+                    if event.state == *state {
+                        hook.on_enter(&event).await;
+                    }
+                }
+            }
+        });
+        (EntryHooks { sender }, task)
+    }
+}
+
+/// The `RiskManager` end of an `AsyncStateHooks` task.
+#[derive(Debug)]
+pub struct EntryHooks {
+    sender: UnboundedSender<RiskEvent>,
+}
+
+impl RiskManager {
+    /// Delivers alerts, entry actions' included, through `notifier` on a task of the current runtime.
+    pub fn with_async_notifier(self, notifier: impl AsyncNotifier + 'static) -> Self {
+        let (notifier, _) = BackgroundNotifier::spawn(notifier);
+        self.with_notifier(Box::new(notifier))
+    }
+
+    /// Runs `hooks` whenever their state is entered, after its synchronous entry actions.
+    pub fn with_async_hooks(mut self, hooks: EntryHooks) -> Self {
+        self.entry_hooks = Some(hooks);
+        self
+    }
+
+    /// Called from `enter_state`: queues the transition just made for the hooks task.
+    pub(crate) fn run_entry_hooks(&self) {
+        if let (Some(hooks), Some(transition)) = (&self.entry_hooks, self.recent_transitions.back()) {
+            let kind = RiskEventKind::StateEntered {
+                from: transition.from,
+                to: transition.to,
+                trigger: transition.trigger,
+                rule: transition.rule,
+                positions: self.book(),
+            };
+            // Once the hooks task is gone there is nobody left to run them.
+            let _ = hooks.sender.send(self.event(kind));
+        }
+    }
+}
+
+impl RiskActor {
+    /// Like `spawn`, with a handle whose methods are `async`. The manager still runs on its own
+    /// thread; ticking is left to the caller, e.g. from a `tokio::time::interval`.
+    pub fn spawn_async<F>(capacity: usize, factory: F) -> Result<(AsyncRiskHandle, thread::JoinHandle<()>), ActorError>
+    where
+        F: FnOnce() -> RiskManager + Send + 'static,
+    {
+        if capacity == 0 {
+            return Err(ActorError::ZeroCapacity);
+        }
+        let (sender, mut receiver) = mpsc::channel(capacity);
+        let metrics = Arc::new(QueueMetrics::new(capacity));
+        let handle = AsyncRiskHandle { sender, metrics: Arc::clone(&metrics) };
+        let thread = thread::spawn(move || {
+            let mut manager = factory();
+            while let Some(request) = receiver.blocking_recv() {
+                metrics.depth.fetch_sub(1, Ordering::SeqCst);
+                if !request.apply(&mut manager) {
+                    break;
+                }
+            }
+        });
+        Ok((handle, thread))
+    }
+}
+
+/// `RiskHandle` for async callers: sends wait for room in the queue without blocking the runtime.
+#[derive(Debug, Clone)]
+pub struct AsyncRiskHandle {
+    sender: mpsc::Sender<Request>,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl AsyncRiskHandle {
+    pub async fn add_position(&self, position_id: &str, size: f64) -> Result<(), ActorError> {
+        self.send(Request::AddPosition { position_id: position_id.to_string(), size }).await
+    }
+
+    pub async fn remove_position(&self, position_id: &str) -> Result<(), ActorError> {
+        self.send(Request::RemovePosition { position_id: position_id.to_string() }).await
+    }
+
+    pub async fn tick(&self) -> Result<(), ActorError> {
+        self.send(Request::Tick).await
+    }
+
+    pub async fn view(&self) -> Result<RiskView, ActorError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::View(Reply::Async(reply))).await?;
+        response.await.map_err(|_| ActorError::Stopped)
+    }
+
+    pub async fn state(&self) -> Result<StateKind, ActorError> {
+        Ok(self.view().await?.state)
+    }
+
+    pub async fn check_order(&self, position_id: &str, size: f64) -> Result<OrderCheck, ActorError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::CheckOrder { position_id: position_id.to_string(), size, reply: Reply::Async(reply) })
+            .await?;
+        response.await.map_err(|_| ActorError::Stopped)?.map_err(ActorError::Var)
+    }
+
+    pub async fn reset_shutdown(&self, request: &ResetRequest) -> Result<(), ActorError> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::ResetShutdown { request: request.clone(), reply: Reply::Async(reply) }).await?;
+        response.await.map_err(|_| ActorError::Stopped)?.map_err(ActorError::Reset)
+    }
+
+    pub async fn stop(&self) -> Result<(), ActorError> {
+        self.send(Request::Stop).await
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.metrics.stats()
+    }
+
+    async fn send(&self, request: Request) -> Result<(), ActorError> {
+        self.metrics.depth.fetch_add(1, Ordering::SeqCst);
+        let sent = match self.sender.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(request)) => {
+                self.metrics.blocked.fetch_add(1, Ordering::SeqCst);
+                self.sender.send(request).await.map_err(|_| ActorError::Stopped)
+            }
+            Err(TrySendError::Closed(_)) => Err(ActorError::Stopped),
+        };
+        self.metrics.settle(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::var_engine::RiskMetric;
+    use crate::state_pattern::DEFAULT_ENGINE;
+    use tokio::sync::Semaphore;
+
+    /// Holds every delivery until the test opens the gate.
+    #[derive(Clone)]
+    struct GatedNotifier {
+        gate: Arc<Semaphore>,
+        delivered: UnboundedSender<String>,
+    }
+
+    impl AsyncNotifier for GatedNotifier {
+        fn deliver<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), NotifyError>> {
+            Box::pin(async move {
+                self.gate.acquire().await.unwrap().forget();
+                let _ = self.delivered.send(alert.subject.clone());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_alerts_and_hooks_do_not_block_updates() {
+        let (placeholder, _) = std::sync::mpsc::channel();
+        let (command_sender, commands) = mpsc::unbounded_channel();
+        let (report_sender, reports) = mpsc::unbounded_channel();
+        let engine = TradingEngine::default().spawn_async(commands, report_sender);
+
+        let gate = Arc::new(Semaphore::new(0));
+        let (delivered, mut deliveries) = mpsc::unbounded_channel();
+        let (hooked, mut hook_runs) = mpsc::unbounded_channel();
+        let hook_gate = Arc::clone(&gate);
+        let (hooks, _) = AsyncStateHooks::new()
+            .on_enter(StateKind::Warning, move |event: RiskEvent| {
+                let (gate, hooked) = (Arc::clone(&hook_gate), hooked.clone());
+                async move {
+                    gate.acquire().await.unwrap().forget();
+                    let _ = hooked.send(event);
+                }
+            })
+            .spawn();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, placeholder)
+            .with_async_notifier(GatedNotifier { gate: Arc::clone(&gate), delivered })
+            .with_async_hooks(hooks);
+        risk_manager.register_engine(DEFAULT_ENGINE, command_sender);
+        risk_manager.attach_reports(DEFAULT_ENGINE, reports);
+
+        // Neither the entry alert nor the hook can finish yet, and the updates still go through.
+        risk_manager.add_position("Position1", 85.0);
+        risk_manager.add_position("Position2", 1.0);
+        assert_eq!(risk_manager.state(), StateKind::Warning);
+        tokio::task::yield_now().await;
+        assert!(deliveries.try_recv().is_err());
+        assert!(hook_runs.try_recv().is_err());
+
+        gate.add_permits(2);
+        assert_eq!(deliveries.recv().await, Some("Warning Level".to_string()));
+        let entered = hook_runs.recv().await.unwrap();
+        assert_eq!(entered.current_var, 85.0);
+        assert!(matches!(
+            entered.kind,
+            RiskEventKind::StateEntered { from: StateKind::Normal, to: StateKind::Warning, trigger: RiskMetric::Var, .. }
+        ));
+        while !risk_manager.unacknowledged().is_empty() {
+            tokio::task::yield_now().await;
+            risk_manager.tick();
+        }
+
+        drop(risk_manager);
+        engine.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_handle() {
+        let (engine_sender, _engine_receiver) = std::sync::mpsc::channel();
+        let (handle, thread) = RiskActor::spawn_async(4, move || RiskManager::new(100.0, 80.0, engine_sender)).unwrap();
+        handle.add_position("Position1", 50.0).await.unwrap();
+        handle.add_position("Position2", 40.0).await.unwrap();
+        assert_eq!(handle.state().await, Ok(StateKind::Warning));
+        assert!(!handle.check_order("Position3", 20.0).await.unwrap().is_approved());
+        handle.stop().await.unwrap();
+        thread.join().unwrap();
+        assert_eq!(handle.tick().await, Err(ActorError::Stopped));
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::Write;
+
+/// VaR attribution of a single position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionAttribution {
+    pub position_id: String,
+    pub size: f64,
+    /// Change in portfolio VaR per unit of size.
+    pub marginal_var: f64,
+    /// Portfolio VaR minus the VaR of the book without this position.
+    pub incremental_var: f64,
+    /// Euler allocation `size * marginal_var`; the components add up to the total VaR.
+    pub component_var: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarAttribution {
+    pub total_var: f64,
+    /// Sorted by component VaR, largest contributor first.
+    pub positions: Vec<PositionAttribution>,
+}
+
+impl VarAttribution {
+    pub fn top_contributors(&self, n: usize) -> &[PositionAttribution] {
+        &self.positions[..n.min(self.positions.len())]
+    }
+
+    /// One-line summary of the `n` largest contributors, for notifications.
+    pub fn report(&self, n: usize) -> String {
+        let mut report = String::from("Top contributors:");
+        for (rank, position) in self.top_contributors(n).iter().enumerate() {
+            let share = if self.total_var != 0.0 { 100.0 * position.component_var / self.total_var } else { 0.0 };
+            let _ = write!(
+                report,
+                "{} {}. {} component {:.2} ({:.1}%), incremental {:.2}",
+                if rank == 0 { "" } else { ";" },
+                rank + 1,
+                position.position_id,
+                position.component_var,
+                share,
+                position.incremental_var
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::state_pattern::var_engine::{CovarianceMatrix, ParametricVar, SummedVar, VarEngine};
+
+    #[test]
+    fn test_components_sum_to_total() {
+        let covariance = CovarianceMatrix::from_correlation(
+            &["rates", "equity"],
+            &[2.0, 3.0],
+            vec![vec![1.0, 0.3], vec![0.3, 1.0]],
+        )
+        .unwrap();
+        let engine = ParametricVar::new(0.99, covariance)
+            .unwrap()
+            .with_exposures("Bond", &[("rates", 10.0)])
+            .with_exposures("Stock", &[("equity", 10.0)])
+            .with_exposures("Hedge", &[("equity", -5.0)]);
+        let positions = HashMap::from([
+            ("Bond".to_string(), 1.0),
+            ("Stock".to_string(), 2.0),
+            ("Hedge".to_string(), 1.0),
+        ]);
+
+        let attribution = engine.attribute(&positions).unwrap();
+        let total: f64 = attribution.positions.iter().map(|p| p.component_var).sum();
+        assert!((total - attribution.total_var).abs() < 1e-9);
+        assert_eq!(attribution.positions[0].position_id, "Stock");
+        let hedge = attribution.positions.iter().find(|p| p.position_id == "Hedge").unwrap();
+        assert!(hedge.component_var < 0.0 && hedge.incremental_var < 0.0 && hedge.marginal_var < 0.0);
+        assert!(attribution.report(2).starts_with("Top contributors: 1. Stock"));
+    }
+
+    #[test]
+    fn test_summed_var_attribution_is_exact() {
+        let positions = HashMap::from([("A".to_string(), 30.0), ("B".to_string(), 70.0)]);
+        let attribution = SummedVar.attribute(&positions).unwrap();
+        assert_eq!(attribution.total_var, 100.0);
+        let b = &attribution.positions[0];
+        assert_eq!(b.position_id, "B");
+        assert!((b.component_var - 70.0).abs() < 1e-6);
+        assert!((b.incremental_var - 70.0).abs() < 1e-9);
+        assert!((b.marginal_var - 1.0).abs() < 1e-6);
+    }
+}
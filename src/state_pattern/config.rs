@@ -0,0 +1,529 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::notifier::{
+    ConsoleNotifier, FanOutNotifier, FileNotifier, Notifier, SeverityFilter, Severity, SmtpNotifier, SyslogFormat,
+    SyslogNotifier, WebhookNotifier,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "config I/O error: {}", err),
+            ConfigError::Parse { line, reason } => write!(f, "config line {}: {}", line, reason),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+/// The thresholds `RiskManager` classifies the book against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskLimits {
+    pub var_limit: f64,
+    pub warning_level: f64,
+    /// Shutdown is entered once a metric reaches its limit times this.
+    pub shutdown_multiplier: f64,
+    pub warning_band: f64,
+    pub limit_band: f64,
+}
+
+impl RiskLimits {
+    /// Requires warning < limit < shutdown, and hysteresis bands that leave a positive exit level;
+    /// LimitBreach must be left above the warning level.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+        let values = [self.var_limit, self.warning_level, self.shutdown_multiplier, self.warning_band, self.limit_band];
+        if values.iter().any(|value| !value.is_finite()) {
+            return invalid("limits must be finite numbers".to_string());
+        }
+        if self.warning_level <= 0.0 || self.warning_level >= self.var_limit {
+            return invalid(format!(
+                "warning level {} must be positive and below the VaR limit {}",
+                self.warning_level, self.var_limit
+            ));
+        }
+        if self.shutdown_multiplier <= 1.0 {
+            return invalid(format!(
+                "shutdown multiplier {} must be above 1 so shutdown lies beyond the limit",
+                self.shutdown_multiplier
+            ));
+        }
+        if self.warning_band < 0.0 || self.warning_band >= self.warning_level {
+            return invalid(format!("warning band {} must lie in [0, {})", self.warning_band, self.warning_level));
+        }
+        let limit_headroom = self.var_limit - self.warning_level;
+        if self.limit_band < 0.0 || self.limit_band > limit_headroom {
+            return invalid(format!(
+                "limit band {} must lie in [0, {}] so LimitBreach exits above the warning level",
+                self.limit_band, limit_headroom
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifierTarget {
+    Console,
+    File { path: PathBuf },
+    Webhook { url: String },
+    Syslog { collector: String, app_name: String },
+    Smtp { server: String, from: String, to: Vec<String> },
+}
+
+/// Sends alerts of at least `min_severity` to `target`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifierRoute {
+    pub min_severity: Severity,
+    pub target: NotifierTarget,
+}
+
+/// Limits and alert routing read from a TOML file:
+///
+/// ```toml
+/// [limits]
+/// var_limit = 100.0
+/// warning_ratio = 0.8        # warning level as a fraction of the limit
+/// shutdown_multiplier = 1.2  # optional, 1.2 by default
+///
+/// [hysteresis]               # optional, no bands by default
+/// warning_band = 5.0
+/// limit_band = 10.0
+///
+/// [[notifier]]               # any number; none keeps the configured notifier
+/// type = "webhook"           # console, file, webhook, syslog or smtp
+/// min_severity = "critical"  # optional, every alert by default
+/// url = "http://alerts.internal/risk"
+/// ```
+///
+/// Only this subset of TOML is understood: tables, arrays of tables, and keys holding
+/// strings, numbers, booleans or single-line arrays of strings. Unknown keys are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskConfig {
+    pub var_limit: f64,
+    pub warning_ratio: f64,
+    pub shutdown_multiplier: f64,
+    pub warning_band: f64,
+    pub limit_band: f64,
+    pub notifiers: Vec<NotifierRoute>,
+}
+
+impl RiskConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses and validates a config.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = RiskConfig {
+            var_limit: f64::NAN,
+            warning_ratio: f64::NAN,
+            shutdown_multiplier: super::DEFAULT_SHUTDOWN_MULTIPLIER,
+            warning_band: 0.0,
+            limit_band: 0.0,
+            notifiers: Vec::new(),
+        };
+        for mut table in parse_toml(text)? {
+            let name = table.name.clone();
+            match (name.as_str(), table.array) {
+                ("limits", false) => {
+                    config.var_limit = table.number("var_limit")?.ok_or_else(|| table.missing("var_limit"))?;
+                    config.warning_ratio = table.number("warning_ratio")?.ok_or_else(|| table.missing("warning_ratio"))?;
+                    if let Some(multiplier) = table.number("shutdown_multiplier")? {
+                        config.shutdown_multiplier = multiplier;
+                    }
+                }
+                ("hysteresis", false) => {
+                    config.warning_band = table.number("warning_band")?.unwrap_or(0.0);
+                    config.limit_band = table.number("limit_band")?.unwrap_or(0.0);
+                }
+                ("notifier", true) => config.notifiers.push(table.route()?),
+                (name, _) => {
+                    return Err(ConfigError::Parse { line: table.line, reason: format!("unknown table [{}]", name) })
+                }
+            }
+            table.finish()?;
+        }
+        if config.var_limit.is_nan() || config.warning_ratio.is_nan() {
+            return Err(ConfigError::Invalid("[limits] with var_limit and warning_ratio is required".to_string()));
+        }
+        if !(config.warning_ratio > 0.0 && config.warning_ratio < 1.0) {
+            return Err(ConfigError::Invalid(format!("warning ratio {} must lie in (0, 1)", config.warning_ratio)));
+        }
+        config.limits().validate()?;
+        Ok(config)
+    }
+
+    pub fn warning_level(&self) -> f64 {
+        self.var_limit * self.warning_ratio
+    }
+
+    pub fn limits(&self) -> RiskLimits {
+        RiskLimits {
+            var_limit: self.var_limit,
+            warning_level: self.warning_level(),
+            shutdown_multiplier: self.shutdown_multiplier,
+            warning_band: self.warning_band,
+            limit_band: self.limit_band,
+        }
+    }
+
+    /// The routes as a single notifier, or None when the config has no routes.
+    pub fn notifier(&self) -> Option<Box<dyn Notifier>> {
+        if self.notifiers.is_empty() {
+            return None;
+        }
+        let mut fan_out = FanOutNotifier::new();
+        for route in &self.notifiers {
+            let notifier: Box<dyn Notifier> = match &route.target {
+                NotifierTarget::Console => Box::new(ConsoleNotifier),
+                NotifierTarget::File { path } => Box::new(FileNotifier::new(path.clone())),
+                NotifierTarget::Webhook { url } => Box::new(WebhookNotifier::new(url)),
+                NotifierTarget::Syslog { collector, app_name } => {
+                    Box::new(SyslogNotifier::new(collector, SyslogFormat::new(app_name)))
+                }
+                NotifierTarget::Smtp { server, from, to } => {
+                    let to: Vec<&str> = to.iter().map(String::as_str).collect();
+                    Box::new(SmtpNotifier::new(server, from, &to))
+                }
+            };
+            fan_out.add(Box::new(SeverityFilter::new(route.min_severity, notifier)));
+        }
+        Some(Box::new(fan_out))
+    }
+}
+
+/// The config file `RiskManager` re-reads from `tick` whenever its modification time changes.
+#[derive(Debug, Clone)]
+pub(crate) struct ConfigWatcher {
+    pub(crate) path: PathBuf,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        ConfigWatcher { path, modified }
+    }
+
+    /// The path to re-read, once per change of the file.
+    pub(crate) fn poll(&mut self) -> Option<PathBuf> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(self.path.clone())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+/// One `[table]` or `[[table]]` of a TOML file. Each getter consumes its key, so `finish`
+/// can reject whatever is left over.
+#[derive(Debug)]
+pub(crate) struct Table {
+    pub(crate) name: String,
+    pub(crate) array: bool,
+    pub(crate) line: usize,
+    entries: BTreeMap<String, (usize, Value)>,
+}
+
+impl Table {
+    pub(crate) fn missing(&self, key: &str) -> ConfigError {
+        ConfigError::Parse { line: self.line, reason: format!("[{}] needs {}", self.name, key) }
+    }
+
+    pub(crate) fn number(&mut self, key: &str) -> Result<Option<f64>, ConfigError> {
+        match self.entries.remove(key) {
+            Some((_, Value::Number(number))) => Ok(Some(number)),
+            Some((line, _)) => Err(ConfigError::Parse { line, reason: format!("{} must be a number", key) }),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn flag(&mut self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.entries.remove(key) {
+            Some((_, Value::Bool(flag))) => Ok(Some(flag)),
+            Some((line, _)) => Err(ConfigError::Parse { line, reason: format!("{} must be true or false", key) }),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn text(&mut self, key: &str) -> Result<String, ConfigError> {
+        self.parse(key, |text| Ok(text.to_string()))?.ok_or_else(|| self.missing(key))
+    }
+
+    /// Parses a string value with `parse`, reporting its errors against the key's line.
+    pub(crate) fn parse<T>(
+        &mut self,
+        key: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, ConfigError> {
+        match self.entries.remove(key) {
+            Some((line, Value::Text(text))) => parse(&text).map(Some).map_err(|reason| ConfigError::Parse { line, reason }),
+            Some((line, _)) => Err(ConfigError::Parse { line, reason: format!("{} must be a string", key) }),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn list(&mut self, key: &str) -> Result<Vec<String>, ConfigError> {
+        match self.entries.remove(key) {
+            Some((_, Value::List(list))) => Ok(list),
+            Some((_, Value::Text(text))) => Ok(vec![text]),
+            Some((line, _)) => Err(ConfigError::Parse { line, reason: format!("{} must be a list of strings", key) }),
+            None => Err(self.missing(key)),
+        }
+    }
+
+    fn route(&mut self) -> Result<NotifierRoute, ConfigError> {
+        let min_severity = self.parse("min_severity", parse_severity)?.unwrap_or(Severity::Info);
+        let line = self.entries.get("type").map_or(self.line, |(line, _)| *line);
+        let target = match self.text("type")?.as_str() {
+            "console" => NotifierTarget::Console,
+            "file" => NotifierTarget::File { path: PathBuf::from(self.text("path")?) },
+            "webhook" => NotifierTarget::Webhook { url: self.text("url")? },
+            "syslog" => NotifierTarget::Syslog { collector: self.text("collector")?, app_name: self.text("app_name")? },
+            "smtp" => NotifierTarget::Smtp { server: self.text("server")?, from: self.text("from")?, to: self.list("to")? },
+            other => return Err(ConfigError::Parse { line, reason: format!("unknown notifier type {}", other) }),
+        };
+        Ok(NotifierRoute { min_severity, target })
+    }
+
+    /// Rejects whatever keys were not consumed, so a misspelt key does not silently fall back to a default.
+    pub(crate) fn finish(self) -> Result<(), ConfigError> {
+        match self.entries.into_iter().next() {
+            Some((key, (line, _))) => {
+                Err(ConfigError::Parse { line, reason: format!("unknown key {} in [{}]", key, self.name) })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn parse_severity(name: &str) -> Result<Severity, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "info" => Ok(Severity::Info),
+        "warning" => Ok(Severity::Warning),
+        "critical" => Ok(Severity::Critical),
+        _ => Err(format!("unknown severity {}", name)),
+    }
+}
+
+pub(crate) fn parse_toml(text: &str) -> Result<Vec<Table>, ConfigError> {
+    let mut tables: Vec<Table> = Vec::new();
+    for (idx, raw) in text.lines().enumerate() {
+        let line = idx + 1;
+        let error = |reason: &str| ConfigError::Parse { line, reason: reason.to_string() };
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+        if let Some(header) = content.strip_prefix('[') {
+            let (name, array) = match header.strip_prefix('[') {
+                Some(name) => (name.strip_suffix("]]").ok_or_else(|| error("unterminated [[table]]"))?, true),
+                None => (header.strip_suffix(']').ok_or_else(|| error("unterminated [table]"))?, false),
+            };
+            let name = name.trim().to_string();
+            if !array && tables.iter().any(|table| table.name == name) {
+                return Err(error(&format!("table [{}] defined twice", name)));
+            }
+            tables.push(Table { name, array, line, entries: BTreeMap::new() });
+            continue;
+        }
+        let (key, value) = content.split_once('=').ok_or_else(|| error("expected key = value"))?;
+        let key = key.trim().to_string();
+        let value = parse_value(value.trim()).map_err(|reason| error(&reason))?;
+        let table = tables.last_mut().ok_or_else(|| error("keys must belong to a table"))?;
+        if table.entries.insert(key.clone(), (line, value)).is_some() {
+            return Err(error(&format!("{} set twice", key)));
+        }
+    }
+    Ok(tables)
+}
+
+/// Drops a trailing `#` comment, leaving any `#` inside a string alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Splits array items on the commas outside strings, like `strip_comment` does for `#`.
+fn split_items(items: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in items.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                parts.push(&items[start..idx]);
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&items[start..]);
+    parts
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(items) = value.strip_prefix('[') {
+        let items = items.strip_suffix(']').ok_or("unterminated array")?.trim();
+        if items.is_empty() {
+            return Ok(Value::List(Vec::new()));
+        }
+        return split_items(items)
+            .into_iter()
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| match parse_value(item)? {
+                Value::Text(text) => Ok(text),
+                _ => Err("arrays may only hold strings".to_string()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    if let Some(text) = value.strip_prefix('"') {
+        let text = text.strip_suffix('"').ok_or("unterminated string")?;
+        let mut unescaped = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('"') => unescaped.push('"'),
+                Some('\\') => unescaped.push('\\'),
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                other => return Err(format!("unsupported escape \\{}", other.map_or(String::new(), String::from))),
+            }
+        }
+        return Ok(Value::Text(unescaped));
+    }
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => (),
+    }
+    value.replace('_', "").parse::<f64>().map(Value::Number).map_err(|_| format!("cannot parse value {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate() {
+        let config = RiskConfig::parse(
+            r#"
+            # desk limits
+            [limits]
+            var_limit = 1_000.0
+            warning_ratio = 0.75
+            shutdown_multiplier = 1.5
+
+            [hysteresis]
+            warning_band = 25
+
+            [[notifier]]
+            type = "file"
+            path = "/var/log/risk#alerts.log"
+
+            [[notifier]]
+            type = "smtp"
+            min_severity = "Critical"
+            server = "mail:25"
+            from = "risk@desk"
+            to = ["ops@desk", "\"Risk, Desk\" <cro@desk>"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.limits(),
+            RiskLimits {
+                var_limit: 1_000.0,
+                warning_level: 750.0,
+                shutdown_multiplier: 1.5,
+                warning_band: 25.0,
+                limit_band: 0.0
+            }
+        );
+        assert_eq!(config.notifiers[0].target, NotifierTarget::File { path: PathBuf::from("/var/log/risk#alerts.log") });
+        assert_eq!(config.notifiers[1].min_severity, Severity::Critical);
+        assert!(matches!(
+            &config.notifiers[1].target,
+            NotifierTarget::Smtp { to, .. } if to == &["ops@desk", "\"Risk, Desk\" <cro@desk>"]
+        ));
+        assert!(config.notifier().is_some());
+
+        let limits = "[limits]\nvar_limit = 100\nwarning_ratio = 0.8\n";
+        assert!(matches!(
+            RiskConfig::parse(&format!("{}shutdown_multiplier = 0.9\n", limits)),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            RiskConfig::parse("[limits]\nvar_limit = 100\nwarning_ratio = 1.1\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            RiskConfig::parse(&format!("{}warning_levl = 70\n", limits)),
+            Err(ConfigError::Parse { line: 4, .. })
+        ));
+        // A limit band wider than the gap to the warning level would exit LimitBreach below warning.
+        assert!(matches!(
+            RiskConfig::parse(&format!("{}\n[hysteresis]\nlimit_band = 25\n", limits)),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeSet;
+
+use super::machine::{Action, StandingCommand, StateMachine, StateSpec, TransitionRule};
+use super::replay::Transition;
+use super::StateKind;
+
+/// Renders a `StateMachine` as a Graphviz (DOT) or Mermaid state diagram: every state with the
+/// command it keeps sending and the commands it sends on entry, and every transition labelled
+/// with its guard. In DOT, transitions taken outside `check_state` (watchdog, operator reset) are dashed.
+///
+/// `RiskManager::diagram` also marks the current state and numbers the recent transitions.
+#[derive(Debug, Clone)]
+pub struct Diagram<'a> {
+    machine: &'a StateMachine,
+    current: Option<StateKind>,
+    path: Vec<Transition>,
+}
+
+impl<'a> Diagram<'a> {
+    pub fn new(machine: &'a StateMachine) -> Self {
+        Diagram { machine, current: None, path: Vec::new() }
+    }
+
+    pub fn with_current(mut self, state: StateKind) -> Self {
+        self.current = Some(state);
+        self
+    }
+
+    /// Highlights the rules the given transitions took, oldest first, numbering them in order.
+    pub fn with_path(mut self, transitions: &[Transition]) -> Self {
+        self.path = transitions.to_vec();
+        self
+    }
+
+    pub fn dot(&self) -> String {
+        let mut out = String::from("digraph risk_states {\n    rankdir=LR;\n    node [shape=box, style=rounded];\n");
+        out.push_str(&format!("    start [shape=point];\n    start -> {:?};\n", StateKind::Normal));
+        for spec in self.machine.states() {
+            let label = state_label(spec).join("\\n");
+            let mut attributes = format!("label=\"{}\"", dot_escape(&label));
+            if self.current == Some(spec.kind) {
+                attributes.push_str(", style=\"rounded,filled\", fillcolor=gold, penwidth=2");
+            } else if self.visited().contains(&spec.kind) {
+                attributes.push_str(", color=red");
+            }
+            out.push_str(&format!("    {:?} [{}];\n", spec.kind, attributes));
+        }
+        for (index, rule) in self.machine.transitions().iter().enumerate() {
+            let steps = self.steps(index);
+            let mut attributes = format!("label=\"{}\"", dot_escape(&self.edge_label(rule, &steps)));
+            if rule.guard.is_external() {
+                attributes.push_str(", style=dashed");
+            }
+            if !steps.is_empty() {
+                attributes.push_str(", color=red, fontcolor=red, penwidth=2");
+            }
+            out.push_str(&format!("    {:?} -> {:?} [{}];\n", rule.from, rule.to, attributes));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn mermaid(&self) -> String {
+        let mut out = String::from("stateDiagram-v2\n    direction LR\n");
+        out.push_str(&format!("    [*] --> {:?}\n", StateKind::Normal));
+        for spec in self.machine.states() {
+            let label = state_label(spec).iter().map(|line| mermaid_escape(line)).collect::<Vec<_>>().join("<br/>");
+            out.push_str(&format!("    state \"{}\" as {:?}\n", label, spec.kind));
+        }
+        for (index, rule) in self.machine.transitions().iter().enumerate() {
+            let steps = self.steps(index);
+            out.push_str(&format!(
+                "    {:?} --> {:?} : {}\n",
+                rule.from,
+                rule.to,
+                mermaid_escape(&self.edge_label(rule, &steps))
+            ));
+        }
+        out.push_str("    classDef current fill:#ffd700,stroke:#333,stroke-width:3px\n");
+        out.push_str("    classDef visited stroke:#d00,stroke-width:2px\n");
+        let visited: Vec<String> = self
+            .visited()
+            .into_iter()
+            .filter(|state| Some(*state) != self.current)
+            .map(|state| format!("{:?}", state))
+            .collect();
+        if !visited.is_empty() {
+            out.push_str(&format!("    class {} visited\n", visited.join(",")));
+        }
+        if let Some(current) = self.current {
+            out.push_str(&format!("    class {:?} current\n", current));
+        }
+        out
+    }
+
+    fn visited(&self) -> BTreeSet<StateKind> {
+        self.path.iter().flat_map(|transition| [transition.from, transition.to]).collect()
+    }
+
+    /// Positions on the path, counted from 1, at which the `index`-th rule was taken. Rules are
+    /// told apart by index, since two of them may join the same pair of states.
+    fn steps(&self, index: usize) -> Vec<usize> {
+        self.path
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| step.rule == Some(index))
+            .map(|(idx, _)| idx + 1)
+            .collect()
+    }
+
+    fn edge_label(&self, rule: &TransitionRule, steps: &[usize]) -> String {
+        let mut label = rule.guard.to_string();
+        if rule.escalates {
+            label.push_str(" (escalates)");
+        }
+        if !steps.is_empty() {
+            let steps: Vec<String> = steps.iter().map(|step| format!("#{}", step)).collect();
+            label.push_str(&format!(" [{}]", steps.join(", ")));
+        }
+        label
+    }
+}
+
+fn command_name(command: &StandingCommand) -> String {
+    match command {
+        StandingCommand::Fixed(command) => format!("{:?}", command),
+        StandingCommand::Throttle => "Throttle".to_string(),
+    }
+}
+
+/// The state's name, its standing command and what it sends or raises on entry.
+fn state_label(spec: &StateSpec) -> Vec<String> {
+    let mut lines = vec![spec.kind.to_string(), format!("sends {}", command_name(&spec.command))];
+    let on_entry: Vec<String> = spec
+        .on_entry
+        .iter()
+        .filter_map(|action| match action {
+            Action::Notify { severity, .. } => Some(format!("{} alert", severity)),
+            Action::Dispatch(command) => Some(format!("{:?}", command)),
+            Action::Liquidate => Some("Liquidate".to_string()),
+            Action::SendCommand => None,
+        })
+        .collect();
+    if !on_entry.is_empty() {
+        lines.push(format!("on entry: {}", on_entry.join(", ")));
+    }
+    lines
+}
+
+fn dot_escape(text: &str) -> String {
+    // `\n` line breaks are already escaped for DOT; only quotes need escaping here.
+    text.replace('"', "\\\"")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;").replace(':', "#colon;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::policy::TransitionPolicy;
+    use crate::state_pattern::{MockClock, RiskManager};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_static_and_runtime_diagrams() {
+        let machine = StateMachine::default();
+        let dot = Diagram::new(&machine).dot();
+        assert!(dot.starts_with("digraph risk_states {"));
+        assert!(dot.contains("    Warning [label=\"Warning Level\\nsends Throttle\\non entry: WARNING alert\"];"));
+        assert!(dot.contains("    Normal -> Warning [label=\"AtWarning and not AtLimit\"];"));
+        assert!(dot.contains("    Shutdown -> Recovery [label=\"OperatorReset\", style=dashed];"));
+        assert!(!dot.contains("fillcolor"));
+        let mermaid = Diagram::new(&machine).mermaid();
+        assert!(mermaid.contains(
+            "    state \"Limit Breach<br/>sends NoTrade<br/>on entry#colon; CRITICAL alert, CancelAllOrders, Liquidate\" as LimitBreach"
+        ));
+        assert!(mermaid.contains("    Warning --> LimitBreach : EscalationDue and not AtLimit and not BelowWarningExit (escalates)"));
+
+        let (sender, _receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        risk_manager.add_position("Position1", 85.0);
+        risk_manager.add_position("Position2", 20.0);
+        risk_manager.remove_position("Position2");
+        let diagram = risk_manager.diagram();
+        let dot = diagram.dot();
+        assert!(dot.contains("    LimitBreach [label=\"Limit Breach\\nsends NoTrade\\non entry: CRITICAL alert, CancelAllOrders, Liquidate\", color=red];"));
+        assert!(dot.contains("fillcolor=gold"));
+        assert!(dot.contains("    Warning -> LimitBreach [label=\"AtLimit and not AtShutdown [#2]\", color=red, fontcolor=red, penwidth=2];"));
+        // The escalation joins the same states but was not taken.
+        assert!(dot.contains("    Warning -> LimitBreach [label=\"EscalationDue and not AtLimit and not BelowWarningExit (escalates)\"];"));
+        let mermaid = diagram.mermaid();
+        assert!(mermaid.contains("    Normal --> Warning : AtWarning and not AtLimit [#1]"));
+        assert!(mermaid.contains("    class Normal,LimitBreach visited\n    class Warning current\n"));
+
+        let (sender, _receiver) = mpsc::channel();
+        let clock = MockClock::default();
+        let policy = TransitionPolicy::new().with_escalation(StateKind::Warning, Duration::from_secs(60));
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender)
+            .with_clock(Box::new(clock.clone()))
+            .with_policy(policy).unwrap();
+        risk_manager.add_position("Position1", 85.0);
+        clock.advance(Duration::from_secs(60));
+        risk_manager.tick();
+        assert_eq!(risk_manager.state(), StateKind::LimitBreach);
+        let dot = risk_manager.diagram().dot();
+        assert!(dot.contains("    Warning -> LimitBreach [label=\"AtLimit and not AtShutdown\"];"));
+        assert!(dot.contains(
+            "    Warning -> LimitBreach [label=\"EscalationDue and not AtLimit and not BelowWarningExit (escalates) [#2]\", color=red, fontcolor=red, penwidth=2];"
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{CommandEnvelope, TradingEngineCommand};
+
+/// Id under which the engine passed to `RiskManager::new` is registered.
+pub const DEFAULT_ENGINE: &str = "default";
+
+/// What the trading engine reports back to `RiskManager`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineReport {
+    /// The command with this sequence number has been applied.
+    Ack { sequence: u64 },
+    /// A position changed size by `quantity` (negative when it was reduced).
+    Fill { position_id: String, quantity: f64 },
+    /// Sent every `heartbeat_interval`, so the risk manager's watchdog knows the engine is alive.
+    Heartbeat,
+}
+
+/// Where `RiskManager` delivers the commands for one engine.
+pub trait CommandSink: fmt::Debug {
+    /// Hands the envelope back if the engine side has gone away.
+    fn deliver(&self, envelope: CommandEnvelope) -> Result<(), CommandEnvelope>;
+}
+
+impl CommandSink for Sender<CommandEnvelope> {
+    fn deliver(&self, envelope: CommandEnvelope) -> Result<(), CommandEnvelope> {
+        self.send(envelope).map_err(|err| err.0)
+    }
+}
+
+/// Where `RiskManager` picks up what one engine reports back. Must not block.
+pub trait ReportSource: fmt::Debug {
+    fn try_report(&mut self) -> Result<EngineReport, TryRecvError>;
+}
+
+impl ReportSource for Receiver<EngineReport> {
+    fn try_report(&mut self) -> Result<EngineReport, TryRecvError> {
+        self.try_recv()
+    }
+}
+
+/// Delivery and acknowledgement status of one engine, as seen by `RiskManager`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineStatus {
+    pub engine_id: String,
+    /// False once its channel has closed.
+    pub connected: bool,
+    /// Number of commands handed to its channel.
+    pub delivered: u64,
+    pub unacknowledged: Vec<u64>,
+    pub last_contact: SystemTime,
+}
+
+/// One engine registered with `RiskManager`.
+#[derive(Debug)]
+pub(crate) struct EngineEndpoint {
+    pub(crate) sender: Box<dyn CommandSink>,
+    pub(crate) reports: Option<Box<dyn ReportSource>>,
+    pub(crate) pending_acks: BTreeMap<u64, (SystemTime, TradingEngineCommand)>,
+    /// Positions it trades; `None` when it is not scoped and receives every liquidation order.
+    pub(crate) holdings: Option<HashSet<String>>,
+    pub(crate) delivered: u64,
+    pub(crate) last_contact: SystemTime,
+    pub(crate) connected: bool,
+}
+
+impl EngineEndpoint {
+    pub(crate) fn new(sender: Box<dyn CommandSink>, now: SystemTime) -> Self {
+        EngineEndpoint {
+            sender,
+            reports: None,
+            pending_acks: BTreeMap::new(),
+            holdings: None,
+            delivered: 0,
+            last_contact: now,
+            connected: true,
+        }
+    }
+
+    /// The part of `command` this engine should receive, if any.
+    pub(crate) fn scoped(&self, command: &TradingEngineCommand) -> Option<TradingEngineCommand> {
+        match (command, &self.holdings) {
+            (TradingEngineCommand::Liquidate(orders), Some(holdings)) => {
+                let orders: Vec<_> = orders.iter().filter(|order| holdings.contains(&order.position_id)).cloned().collect();
+                if orders.is_empty() {
+                    None
+                } else {
+                    Some(TradingEngineCommand::Liquidate(orders))
+                }
+            }
+            _ => Some(command.clone()),
+        }
+    }
+
+    pub(crate) fn status(&self, engine_id: &str) -> EngineStatus {
+        EngineStatus {
+            engine_id: engine_id.to_string(),
+            connected: self.connected,
+            delivered: self.delivered,
+            unacknowledged: self.pending_acks.keys().copied().collect(),
+            last_contact: self.last_contact,
+        }
+    }
+}
+
+/// A stand-in trading engine running on its own thread.
+///
+/// It heartbeats to the risk manager and reports on stdout when nothing has arrived from the
+/// risk manager for `manager_timeout`. It has no trading of its own to halt: the next command
+/// that arrives is executed as usual. The thread ends when the command channel closes, i.e.
+/// when the `RiskManager` is dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct TradingEngine {
+    pub heartbeat_interval: Duration,
+    pub manager_timeout: Duration,
+}
+
+impl Default for TradingEngine {
+    fn default() -> Self {
+        TradingEngine {
+            heartbeat_interval: Duration::from_secs(1),
+            manager_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TradingEngine {
+    pub fn start(receiver: Receiver<CommandEnvelope>, reports: Sender<EngineReport>) -> JoinHandle<()> {
+        TradingEngine::default().spawn(receiver, reports)
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat_interval: Duration, manager_timeout: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self.manager_timeout = manager_timeout;
+        self
+    }
+
+    pub fn spawn(self, receiver: Receiver<CommandEnvelope>, reports: Sender<EngineReport>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            println!("Trading engine started.");
+            let mut last_heard = Instant::now();
+            let mut last_heartbeat = Instant::now();
+            let mut silent = false;
+            // Keep listening after StopEngine so an operator reset can bring trading back.
+            loop {
+                match receiver.recv_timeout(self.heartbeat_interval) {
+                    Ok(envelope) => {
+                        last_heard = Instant::now();
+                        if silent {
+                            println!("Risk manager is back.");
+                            silent = false;
+                        }
+                        // The risk manager may not be listening for reports; that is not an error for the engine.
+                        for report in execute(envelope) {
+                            let _ = reports.send(report);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if !silent && last_heard.elapsed() >= self.manager_timeout {
+                    println!("Nothing heard from the risk manager for {:?}.", self.manager_timeout);
+                    silent = true;
+                }
+                if last_heartbeat.elapsed() >= self.heartbeat_interval {
+                    last_heartbeat = Instant::now();
+                    let _ = reports.send(EngineReport::Heartbeat);
+                }
+            }
+            println!("Trading engine stopped.");
+        })
+    }
+}
+
+/// Applies one command and returns the reports it produces, ending with its acknowledgement.
+pub(crate) fn execute(envelope: CommandEnvelope) -> Vec<EngineReport> {
+    let mut reports = Vec::new();
+    if envelope.command != TradingEngineCommand::Heartbeat {
+        print!("#{} from {}: ", envelope.sequence, envelope.origin);
+    }
+    match envelope.command {
+        TradingEngineCommand::ExecuteTrade => {
+            println!("Executing trade");
+        }
+        TradingEngineCommand::StopEngine => {
+            println!("Stopping trading engine.");
+            // Perform cleanup if necessary
+        }
+        TradingEngineCommand::NoTrade => {
+            println!("No trade to execute.");
+        }
+        TradingEngineCommand::ReduceOnly => {
+            println!("Reduce-only mode: only closing orders are accepted.");
+        }
+        TradingEngineCommand::Throttle(limits) => {
+            println!(
+                "Throttling: max order size {:.2}, max {} orders per second.",
+                limits.max_order_size, limits.max_orders_per_second
+            );
+        }
+        TradingEngineCommand::CancelAllOrders => {
+            println!("Cancelling all open orders.");
+        }
+        TradingEngineCommand::Liquidate(orders) => {
+            for order in orders {
+                println!("Reducing {} by {:.2}", order.position_id, order.quantity);
+                reports.push(EngineReport::Fill {
+                    position_id: order.position_id,
+                    quantity: -order.quantity,
+                });
+            }
+        }
+        TradingEngineCommand::Resume => {
+            println!("Restrictions lifted, resuming normal trading.");
+        }
+        TradingEngineCommand::Heartbeat => {
+            reports.push(EngineReport::Heartbeat);
+        }
+    }
+    reports.push(EngineReport::Ack { sequence: envelope.sequence });
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::StateKind;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_engine_stops_cleanly_when_risk_manager_goes_away() {
+        let (sender, receiver) = mpsc::channel();
+        let (report_sender, reports) = mpsc::channel();
+        let handle = TradingEngine::default()
+            .with_heartbeat(Duration::from_millis(5), Duration::from_secs(1))
+            .spawn(receiver, report_sender);
+        sender
+            .send(CommandEnvelope {
+                sequence: 7,
+                timestamp: SystemTime::now(),
+                origin: StateKind::Normal,
+                command: TradingEngineCommand::Heartbeat,
+            })
+            .unwrap();
+        // The acknowledgement and the engine's own heartbeats, however slowly the thread is scheduled.
+        let mut received = Vec::new();
+        let heartbeats =
+            |received: &[EngineReport]| received.iter().filter(|report| **report == EngineReport::Heartbeat).count();
+        while !received.contains(&EngineReport::Ack { sequence: 7 }) || heartbeats(&received) < 2 {
+            received.push(reports.recv_timeout(Duration::from_secs(10)).unwrap());
+        }
+        drop(sender);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::config::RiskLimits;
+use super::var_engine::RiskMetric;
+use super::{StateKind, TradingEngineCommand};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskEventKind {
+    StateExited { from: StateKind, to: StateKind },
+    /// `trigger` is the metric that was worst relative to its limit when the state was entered,
+    /// `positions` the book at that moment. `rule` is the index of the transition taken in
+    /// `StateMachine::transitions`, if the state was entered through one.
+    StateEntered { from: StateKind, to: StateKind, trigger: RiskMetric, rule: Option<usize>, positions: BTreeMap<String, f64> },
+    /// A de-escalation was held back because the minimum dwell time has not elapsed yet.
+    TransitionSuppressed { from: StateKind, to: StateKind, remaining: Duration },
+    PositionAdded { position_id: String, var_contribution: f64 },
+    PositionRemoved { position_id: String },
+    VarUpdated { previous_var: f64 },
+    ShutdownReset { operator_id: String, reason: String },
+    ResetRejected { operator_id: String, reason: String, error: String },
+    CommandSent { sequence: u64, command: TradingEngineCommand },
+    CommandAcknowledged { engine_id: String, sequence: u64 },
+    /// The engine did not acknowledge the command in time; the alert has been escalated.
+    AckTimedOut { engine_id: String, sequence: u64, command: TradingEngineCommand },
+    PositionFilled { engine_id: String, position_id: String, quantity: f64 },
+    EngineRegistered { engine_id: String },
+    EngineDeregistered { engine_id: String },
+    /// `RiskManager::restore` resumed from a snapshot taken at `taken_at`.
+    Restored { taken_at: SystemTime },
+    /// New limits came into force, e.g. from a config reload.
+    LimitsChanged { previous: RiskLimits, limits: RiskLimits },
+}
+
+/// A typed notification emitted by `RiskManager`, stamped with the limits in force when it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskEvent {
+    pub timestamp: SystemTime,
+    pub state: StateKind,
+    pub current_var: f64,
+    pub current_es: Option<f64>,
+    pub var_limit: f64,
+    pub warning_level: f64,
+    pub kind: RiskEventKind,
+}
+
+pub trait RiskEventSubscriber {
+    fn on_event(&self, event: &RiskEvent);
+}
+
+impl<F: Fn(&RiskEvent)> RiskEventSubscriber for F {
+    fn on_event(&self, event: &RiskEvent) {
+        self(event)
+    }
+}
+
+/// Forwards every event into an mpsc channel, e.g. towards a dashboard thread.
+pub struct ChannelSubscriber(pub Sender<RiskEvent>);
+
+impl RiskEventSubscriber for ChannelSubscriber {
+    fn on_event(&self, event: &RiskEvent) {
+        // A dropped receiver only means nobody is listening any more.
+        let _ = self.0.send(event.clone());
+    }
+}
+
+/// Keeps every event in memory. Clones share the same buffer.
+#[derive(Debug, Clone, Default)]
+pub struct EventRecorder {
+    events: Arc<Mutex<Vec<RiskEvent>>>,
+}
+
+impl EventRecorder {
+    pub fn new() -> Self {
+        EventRecorder::default()
+    }
+
+    pub fn events(&self) -> Vec<RiskEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+impl RiskEventSubscriber for EventRecorder {
+    fn on_event(&self, event: &RiskEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+#[derive(Default)]
+pub struct EventBus {
+    next_id: u64,
+    subscribers: Vec<(SubscriptionId, Box<dyn RiskEventSubscriber>)>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    pub fn subscribe(&mut self, subscriber: Box<dyn RiskEventSubscriber>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, subscriber));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|(subscription, _)| *subscription != id);
+        self.subscribers.len() != before
+    }
+
+    pub fn publish(&self, event: &RiskEvent) {
+        for (_, subscriber) in &self.subscribers {
+            subscriber.on_event(event);
+        }
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscribers.len())
+            .finish()
+    }
+}
@@ -0,0 +1,1005 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::events::{RiskEvent, RiskEventKind, RiskEventSubscriber};
+use super::notifier::{format_rfc3339, json_escape};
+use super::StateKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalFormat {
+    /// One JSON object per line: `{"crc32":"…","record":{…}}`, the checksum covering the record text.
+    JsonLines,
+    /// Length prefixed records, each followed by the CRC-32 of its payload. All integers little endian.
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    /// Record number `record` (counting from 0) failed its checksum or could not be decoded.
+    Corrupt { record: usize, reason: String },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(err) => write!(f, "journal I/O error: {}", err),
+            JournalError::Corrupt { record, reason } => write!(f, "journal record {} is corrupt: {}", record, reason),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(err: io::Error) -> Self {
+        JournalError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Text(String),
+    Number(f64),
+    Integer(u64),
+}
+
+/// One journal record: a `RiskEvent` flattened into fields that both formats can hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: SystemTime,
+    /// State of the manager once the event had happened.
+    pub state: StateKind,
+    /// Name of the `RiskEventKind` variant, e.g. `"StateEntered"`.
+    pub event: String,
+    pub current_var: f64,
+    pub current_es: Option<f64>,
+    pub var_limit: f64,
+    pub warning_level: f64,
+    pub details: BTreeMap<String, Field>,
+    /// The book when a state was entered; empty for every other event.
+    pub positions: BTreeMap<String, f64>,
+}
+
+impl JournalEntry {
+    fn from_event(sequence: u64, event: &RiskEvent) -> Self {
+        let (name, details) = describe(&event.kind);
+        let positions = match &event.kind {
+            RiskEventKind::StateEntered { positions, .. } => positions.clone(),
+            _ => BTreeMap::new(),
+        };
+        JournalEntry {
+            sequence,
+            timestamp: event.timestamp,
+            state: event.state,
+            event: name.to_string(),
+            current_var: event.current_var,
+            current_es: event.current_es,
+            var_limit: event.var_limit,
+            warning_level: event.warning_level,
+            details: details.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+            positions,
+        }
+    }
+
+    pub fn detail(&self, key: &str) -> Option<&Field> {
+        self.details.get(key)
+    }
+}
+
+fn state_name(state: StateKind) -> Field {
+    Field::Text(format!("{:?}", state))
+}
+
+fn describe(kind: &RiskEventKind) -> (&'static str, Vec<(&'static str, Field)>) {
+    let text = |value: &str| Field::Text(value.to_string());
+    match kind {
+        RiskEventKind::StateExited { from, to } => ("StateExited", vec![("from", state_name(*from)), ("to", state_name(*to))]),
+        RiskEventKind::StateEntered { from, to, trigger, .. } => (
+            "StateEntered",
+            vec![("from", state_name(*from)), ("to", state_name(*to)), ("trigger", text(&trigger.to_string()))],
+        ),
+        RiskEventKind::TransitionSuppressed { from, to, remaining } => (
+            "TransitionSuppressed",
+            vec![
+                ("from", state_name(*from)),
+                ("to", state_name(*to)),
+                ("remaining_ms", Field::Integer(remaining.as_millis() as u64)),
+            ],
+        ),
+        RiskEventKind::PositionAdded { position_id, var_contribution } => (
+            "PositionAdded",
+            vec![("position_id", text(position_id)), ("var_contribution", Field::Number(*var_contribution))],
+        ),
+        RiskEventKind::PositionRemoved { position_id } => ("PositionRemoved", vec![("position_id", text(position_id))]),
+        RiskEventKind::VarUpdated { previous_var } => ("VarUpdated", vec![("previous_var", Field::Number(*previous_var))]),
+        RiskEventKind::ShutdownReset { operator_id, reason } => {
+            ("ShutdownReset", vec![("operator_id", text(operator_id)), ("reason", text(reason))])
+        }
+        RiskEventKind::ResetRejected { operator_id, reason, error } => (
+            "ResetRejected",
+            vec![("operator_id", text(operator_id)), ("reason", text(reason)), ("error", text(error))],
+        ),
+        RiskEventKind::CommandSent { sequence, command } => (
+            "CommandSent",
+            vec![("command_sequence", Field::Integer(*sequence)), ("command", text(&format!("{:?}", command)))],
+        ),
+        RiskEventKind::CommandAcknowledged { engine_id, sequence } => (
+            "CommandAcknowledged",
+            vec![("engine_id", text(engine_id)), ("command_sequence", Field::Integer(*sequence))],
+        ),
+        RiskEventKind::AckTimedOut { engine_id, sequence, command } => (
+            "AckTimedOut",
+            vec![
+                ("engine_id", text(engine_id)),
+                ("command_sequence", Field::Integer(*sequence)),
+                ("command", text(&format!("{:?}", command))),
+            ],
+        ),
+        RiskEventKind::PositionFilled { engine_id, position_id, quantity } => (
+            "PositionFilled",
+            vec![("engine_id", text(engine_id)), ("position_id", text(position_id)), ("quantity", Field::Number(*quantity))],
+        ),
+        RiskEventKind::EngineRegistered { engine_id } => ("EngineRegistered", vec![("engine_id", text(engine_id))]),
+        RiskEventKind::EngineDeregistered { engine_id } => ("EngineDeregistered", vec![("engine_id", text(engine_id))]),
+        RiskEventKind::Restored { taken_at } => {
+            ("Restored", vec![("taken_at_ns", Field::Integer(nanos_since_epoch(*taken_at)))])
+        }
+        RiskEventKind::LimitsChanged { previous, limits } => (
+            "LimitsChanged",
+            vec![
+                ("previous_var_limit", Field::Number(previous.var_limit)),
+                ("previous_warning_level", Field::Number(previous.warning_level)),
+                ("previous_shutdown_multiplier", Field::Number(previous.shutdown_multiplier)),
+                ("shutdown_multiplier", Field::Number(limits.shutdown_multiplier)),
+                ("warning_band", Field::Number(limits.warning_band)),
+                ("limit_band", Field::Number(limits.limit_band)),
+            ],
+        ),
+    }
+}
+
+/// Appends every event it is subscribed to, and the book on each transition, to a journal file.
+///
+/// Records are flushed one by one; `with_fsync` also forces them to disk. Write failures are
+/// reported on stderr, as `RiskManager` does for alerts, since subscribers cannot fail.
+#[derive(Debug)]
+pub struct JournalWriter {
+    path: PathBuf,
+    format: JournalFormat,
+    fsync: bool,
+    torn: Option<TornRecord>,
+    inner: Mutex<WriterState>,
+}
+
+#[derive(Debug)]
+struct WriterState {
+    file: File,
+    next_sequence: u64,
+}
+
+/// A last record left incomplete by a crash during an append, cut off when the journal was reopened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TornRecord {
+    /// Number of the record, counting from 0.
+    pub record: usize,
+    /// Byte offset the file was truncated to.
+    pub offset: u64,
+    pub discarded_bytes: u64,
+    pub reason: String,
+}
+
+impl JournalWriter {
+    /// Opens `path` for appending, numbering new records after the ones already in it.
+    ///
+    /// A torn last record is truncated away and reported on stderr and by `torn_record`;
+    /// corruption anywhere before it is an error.
+    pub fn open(path: impl AsRef<Path>, format: JournalFormat) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let scan = scan(&bytes, format)?;
+        let torn = scan.torn.map(|(record, reason)| TornRecord {
+            record,
+            offset: scan.valid_len as u64,
+            discarded_bytes: (bytes.len() - scan.valid_len) as u64,
+            reason,
+        });
+        if let Some(torn) = &torn {
+            file.set_len(torn.offset)?;
+            eprintln!(
+                "Truncated torn journal record {} ({} bytes) in {}: {}",
+                torn.record,
+                torn.discarded_bytes,
+                path.display(),
+                torn.reason
+            );
+        }
+        let next_sequence = scan.entries.last().map_or(0, |entry| entry.sequence + 1);
+        Ok(JournalWriter {
+            path,
+            format,
+            fsync: false,
+            torn,
+            inner: Mutex::new(WriterState { file, next_sequence }),
+        })
+    }
+
+    /// The incomplete record `open` cut off, if there was one.
+    pub fn torn_record(&self) -> Option<&TornRecord> {
+        self.torn.as_ref()
+    }
+
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&self, event: &RiskEvent) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = JournalEntry::from_event(inner.next_sequence, event);
+        let bytes = match self.format {
+            JournalFormat::JsonLines => encode_json_line(&entry).into_bytes(),
+            JournalFormat::Binary => encode_binary(&entry),
+        };
+        inner.file.write_all(&bytes)?;
+        inner.file.flush()?;
+        if self.fsync {
+            inner.file.sync_data()?;
+        }
+        inner.next_sequence += 1;
+        Ok(())
+    }
+}
+
+impl RiskEventSubscriber for JournalWriter {
+    fn on_event(&self, event: &RiskEvent) {
+        if let Err(err) = self.append(event) {
+            eprintln!("Failed to journal risk event to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Narrows `JournalReader::query` down; every criterion left unset matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct JournalQuery {
+    pub from: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    pub state: Option<StateKind>,
+    pub event: Option<String>,
+}
+
+impl JournalQuery {
+    pub fn new() -> Self {
+        JournalQuery::default()
+    }
+
+    /// Inclusive lower bound.
+    pub fn from(mut self, from: SystemTime) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    /// Exclusive upper bound.
+    pub fn until(mut self, until: SystemTime) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn state(mut self, state: StateKind) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self.state.is_none_or(|state| entry.state == state)
+            && self.event.as_ref().is_none_or(|event| entry.event == *event)
+    }
+}
+
+/// A journal read back into memory, every record's checksum verified.
+#[derive(Debug, Clone)]
+pub struct JournalReader {
+    entries: Vec<JournalEntry>,
+}
+
+impl JournalReader {
+    pub fn open(path: impl AsRef<Path>, format: JournalFormat) -> Result<Self, JournalError> {
+        Self::from_reader(File::open(path)?, format)
+    }
+
+    pub fn from_reader(reader: impl Read, format: JournalFormat) -> Result<Self, JournalError> {
+        let entries = match format {
+            JournalFormat::JsonLines => {
+                let mut entries = Vec::new();
+                for (record, line) in BufReader::new(reader).lines().enumerate() {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        entries.push(decode_json_line(&line).map_err(|reason| JournalError::Corrupt { record, reason })?);
+                    }
+                }
+                entries
+            }
+            JournalFormat::Binary => {
+                let mut bytes = Vec::new();
+                BufReader::new(reader).read_to_end(&mut bytes)?;
+                decode_binary(&bytes)?
+            }
+        };
+        Ok(JournalReader { entries })
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn query(&self, query: &JournalQuery) -> Vec<&JournalEntry> {
+        self.entries.iter().filter(|entry| query.matches(entry)).collect()
+    }
+
+    pub fn between(&self, from: SystemTime, until: SystemTime) -> Vec<&JournalEntry> {
+        self.query(&JournalQuery::new().from(from).until(until))
+    }
+
+    pub fn in_state(&self, state: StateKind) -> Vec<&JournalEntry> {
+        self.query(&JournalQuery::new().state(state))
+    }
+}
+
+/// What `scan_records` found in an existing journal or event log.
+pub(crate) struct Scan<T> {
+    pub(crate) entries: Vec<T>,
+    /// Length of the prefix made of complete, valid records.
+    pub(crate) valid_len: usize,
+    /// The record after that prefix, if it is the last one and incomplete or corrupt.
+    pub(crate) torn: Option<(usize, String)>,
+}
+
+fn scan(bytes: &[u8], format: JournalFormat) -> Result<Scan<JournalEntry>, JournalError> {
+    scan_records(bytes, |pos| read_record(bytes, pos, format))
+}
+
+/// Reads `bytes` record by record with `read`, which works like `read_record`.
+pub(crate) fn scan_records<T>(
+    bytes: &[u8],
+    read: impl Fn(usize) -> Result<(usize, Option<T>), (String, bool)>,
+) -> Result<Scan<T>, JournalError> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let record = entries.len();
+        match read(pos) {
+            Ok((end, entry)) => {
+                entries.extend(entry);
+                pos = end;
+            }
+            Err((reason, true)) => return Ok(Scan { entries, valid_len: pos, torn: Some((record, reason)) }),
+            Err((reason, false)) => return Err(JournalError::Corrupt { record, reason }),
+        }
+    }
+    Ok(Scan { entries, valid_len: bytes.len(), torn: None })
+}
+
+/// The record starting at `pos`: where it ends and its entry (`None` for a blank line), or why
+/// it cannot be read and whether it is the last record in the file.
+fn read_record(bytes: &[u8], pos: usize, format: JournalFormat) -> Result<(usize, Option<JournalEntry>), (String, bool)> {
+    match format {
+        JournalFormat::JsonLines => read_json_line(bytes, pos, decode_json_line),
+        JournalFormat::Binary => {
+            // A record running past the end is torn only if it is the last one: a damaged length
+            // prefix also runs past the end, but leaves intact records behind it.
+            let torn = |reason: String| {
+                let last = !(pos + 1..bytes.len()).any(|start| binary_record_at(bytes, start).is_ok());
+                (reason, last)
+            };
+            let mut cursor = Cursor { bytes, pos };
+            let len = cursor.u32().map_err(torn)? as usize;
+            let payload = cursor.take(len).map_err(torn)?;
+            let expected = cursor.u32().map_err(torn)?;
+            let at_end = cursor.pos == bytes.len();
+            if crc32(payload) != expected {
+                return Err(("checksum mismatch".to_string(), at_end));
+            }
+            decode_payload(payload).map(|entry| (cursor.pos, Some(entry))).map_err(|reason| (reason, at_end))
+        }
+    }
+}
+
+/// The line starting at `pos`, decoded with `decode`; see `read_record`.
+pub(crate) fn read_json_line<T>(
+    bytes: &[u8],
+    pos: usize,
+    decode: impl Fn(&str) -> Result<T, String>,
+) -> Result<(usize, Option<T>), (String, bool)> {
+    let Some(len) = bytes[pos..].iter().position(|byte| *byte == b'\n') else {
+        // Every record ends with its newline; without one the append did not finish.
+        return Err(("record is missing its line end".to_string(), true));
+    };
+    let end = pos + len + 1;
+    let line = String::from_utf8_lossy(&bytes[pos..pos + len]);
+    if line.trim().is_empty() {
+        return Ok((end, None));
+    }
+    decode(&line).map(|entry| (end, Some(entry))).map_err(|reason| (reason, end == bytes.len()))
+}
+
+/// The complete, checksum-valid binary record starting at `pos`, if there is one.
+fn binary_record_at(bytes: &[u8], pos: usize) -> Result<JournalEntry, String> {
+    let mut cursor = Cursor { bytes, pos };
+    let len = cursor.u32()? as usize;
+    let payload = cursor.take(len)?;
+    if crc32(payload) != cursor.u32()? {
+        return Err("checksum mismatch".to_string());
+    }
+    decode_payload(payload)
+}
+
+/// CRC-32 (IEEE 802.3), as used by zip and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub(crate) fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+pub(crate) fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn encode_json_line(entry: &JournalEntry) -> String {
+    let mut record = format!(
+        "{{\"sequence\":{},\"timestamp_ns\":{},\"time\":\"{}\",\"state\":\"{:?}\",\"event\":\"{}\",\"current_var\":{},\"current_es\":{},\"var_limit\":{},\"warning_level\":{},\"details\":{{",
+        entry.sequence,
+        nanos_since_epoch(entry.timestamp),
+        format_rfc3339(entry.timestamp),
+        entry.state,
+        json_escape(&entry.event),
+        json_number(entry.current_var),
+        entry.current_es.map_or("null".to_string(), json_number),
+        json_number(entry.var_limit),
+        json_number(entry.warning_level),
+    );
+    let details: Vec<String> = entry
+        .details
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Field::Text(text) => format!("\"{}\"", json_escape(text)),
+                Field::Number(number) => json_number(*number),
+                Field::Integer(integer) => integer.to_string(),
+            };
+            format!("\"{}\":{}", json_escape(key), value)
+        })
+        .collect();
+    record.push_str(&details.join(","));
+    record.push_str("},\"positions\":{");
+    let positions: Vec<String> = entry
+        .positions
+        .iter()
+        .map(|(id, size)| format!("\"{}\":{}", json_escape(id), json_number(*size)))
+        .collect();
+    record.push_str(&positions.join(","));
+    record.push_str("}}");
+    format!("{{\"crc32\":\"{:08x}\",\"record\":{}}}\n", crc32(record.as_bytes()), record)
+}
+
+fn decode_json_line(line: &str) -> Result<JournalEntry, String> {
+    let line = line.trim_end();
+    let prefix = "{\"crc32\":\"";
+    if !line.starts_with(prefix) || line.len() < prefix.len() + 8 {
+        return Err("missing checksum".to_string());
+    }
+    let expected = u32::from_str_radix(&line[prefix.len()..prefix.len() + 8], 16).map_err(|err| err.to_string())?;
+    let record = line[prefix.len() + 8..]
+        .strip_prefix("\",\"record\":")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or_else(|| "malformed record".to_string())?;
+    if crc32(record.as_bytes()) != expected {
+        return Err("checksum mismatch".to_string());
+    }
+
+    let object = match Json::parse(record)? {
+        Json::Object(object) => object,
+        _ => return Err("record is not an object".to_string()),
+    };
+    let number = |key: &str| match object.get(key) {
+        Some(Json::Number(number)) => Ok(*number),
+        Some(Json::Null) => Ok(f64::NAN),
+        _ => Err(format!("missing {}", key)),
+    };
+    let text = |key: &str| match object.get(key) {
+        Some(Json::Text(text)) => Ok(text.clone()),
+        _ => Err(format!("missing {}", key)),
+    };
+    let members = |key: &str| match object.get(key) {
+        Some(Json::Object(members)) => Ok(members.clone()),
+        _ => Err(format!("missing {}", key)),
+    };
+    let details = members("details")?
+        .into_iter()
+        .map(|(key, value)| match value {
+            Json::Text(text) => Ok((key, Field::Text(text))),
+            Json::Integer(integer) => Ok((key, Field::Integer(integer))),
+            Json::Number(number) => Ok((key, Field::Number(number))),
+            Json::Null => Ok((key, Field::Number(f64::NAN))),
+            Json::Object(_) => Err(format!("nested detail {}", key)),
+        })
+        .collect::<Result<_, String>>()?;
+    let positions = members("positions")?
+        .into_iter()
+        .map(|(id, size)| match size {
+            Json::Number(size) => Ok((id, size)),
+            Json::Integer(size) => Ok((id, size as f64)),
+            _ => Err(format!("bad size for {}", id)),
+        })
+        .collect::<Result<_, String>>()?;
+    let integer = |key: &str| match object.get(key) {
+        Some(Json::Integer(integer)) => Ok(*integer),
+        _ => Err(format!("missing {}", key)),
+    };
+    Ok(JournalEntry {
+        sequence: integer("sequence")?,
+        timestamp: UNIX_EPOCH + Duration::from_nanos(integer("timestamp_ns")?),
+        state: text("state")?.parse()?,
+        event: text("event")?,
+        current_var: number("current_var")?,
+        current_es: match object.get("current_es") {
+            Some(Json::Null) | None => None,
+            _ => Some(number("current_es")?),
+        },
+        var_limit: number("var_limit")?,
+        warning_level: number("warning_level")?,
+        details,
+        positions,
+    })
+}
+
+/// Just enough JSON for the records written above.
+#[derive(Debug, Clone)]
+pub(crate) enum Json {
+    Null,
+    Integer(u64),
+    Number(f64),
+    Text(String),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    pub(crate) fn parse(input: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { chars: input.chars().collect(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err("trailing characters".to_string());
+        }
+        Ok(value)
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some(c) if *c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            other => Err(format!("expected '{}' at {}, found {:?}", expected, self.pos, other)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some('{') => self.object(),
+            Some('"') => Ok(Json::Text(self.string()?)),
+            Some('n') if self.chars[self.pos..].starts_with(&['n', 'u', 'l', 'l']) => {
+                self.pos += 4;
+                Ok(Json::Null)
+            }
+            Some(_) => self.number(),
+            None => Err("unexpected end of record".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            members.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.chars.get(self.pos) {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                other => return Err(format!("expected ',' or '}}' at {}, found {:?}", self.pos, other)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            let c = *self.chars.get(self.pos).ok_or("unterminated string")?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escaped = *self.chars.get(self.pos).ok_or("unterminated escape")?;
+                    self.pos += 1;
+                    match escaped {
+                        'n' => text.push('\n'),
+                        'r' => text.push('\r'),
+                        't' => text.push('\t'),
+                        'b' => text.push('\u{8}'),
+                        'f' => text.push('\u{c}'),
+                        'u' => {
+                            let hex: String = self.chars.get(self.pos..self.pos + 4).ok_or("short \\u escape")?.iter().collect();
+                            self.pos += 4;
+                            let code = u32::from_str_radix(&hex, 16).map_err(|err| err.to_string())?;
+                            text.push(char::from_u32(code).ok_or("invalid \\u escape")?);
+                        }
+                        other => text.push(other),
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+            self.pos += 1;
+        }
+        let literal: String = self.chars[start..self.pos].iter().collect();
+        if literal.chars().all(|c| c.is_ascii_digit()) && !literal.is_empty() {
+            literal.parse().map(Json::Integer).map_err(|err| format!("{}: {}", literal, err))
+        } else {
+            literal.parse().map(Json::Number).map_err(|_| format!("invalid number '{}' at {}", literal, start))
+        }
+    }
+}
+
+const FIELD_TEXT: u8 = 0;
+const FIELD_NUMBER: u8 = 1;
+const FIELD_INTEGER: u8 = 2;
+
+fn put_text(buffer: &mut Vec<u8>, text: &str) {
+    buffer.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+/// `[payload length: u32][payload][crc32 of payload: u32]`.
+fn encode_binary(entry: &JournalEntry) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&entry.sequence.to_le_bytes());
+    payload.extend_from_slice(&nanos_since_epoch(entry.timestamp).to_le_bytes());
+    let state = StateKind::ALL.iter().position(|state| *state == entry.state).unwrap_or_default();
+    payload.push(state as u8);
+    put_text(&mut payload, &entry.event);
+    payload.extend_from_slice(&entry.current_var.to_le_bytes());
+    match entry.current_es {
+        Some(es) => {
+            payload.push(1);
+            payload.extend_from_slice(&es.to_le_bytes());
+        }
+        None => payload.push(0),
+    }
+    payload.extend_from_slice(&entry.var_limit.to_le_bytes());
+    payload.extend_from_slice(&entry.warning_level.to_le_bytes());
+    payload.extend_from_slice(&(entry.details.len() as u32).to_le_bytes());
+    for (key, value) in &entry.details {
+        put_text(&mut payload, key);
+        match value {
+            Field::Text(text) => {
+                payload.push(FIELD_TEXT);
+                put_text(&mut payload, text);
+            }
+            Field::Number(number) => {
+                payload.push(FIELD_NUMBER);
+                payload.extend_from_slice(&number.to_le_bytes());
+            }
+            Field::Integer(integer) => {
+                payload.push(FIELD_INTEGER);
+                payload.extend_from_slice(&integer.to_le_bytes());
+            }
+        }
+    }
+    payload.extend_from_slice(&(entry.positions.len() as u32).to_le_bytes());
+    for (id, size) in &entry.positions {
+        put_text(&mut payload, id);
+        payload.extend_from_slice(&size.to_le_bytes());
+    }
+
+    let mut record = Vec::with_capacity(payload.len() + 8);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or("truncated record")?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|err| err.to_string())
+    }
+}
+
+fn decode_binary(bytes: &[u8]) -> Result<Vec<JournalEntry>, JournalError> {
+    let mut entries = Vec::new();
+    let mut cursor = Cursor { bytes, pos: 0 };
+    while cursor.pos < bytes.len() {
+        let record = entries.len();
+        let corrupt = |reason: String| JournalError::Corrupt { record, reason };
+        let len = cursor.u32().map_err(corrupt)? as usize;
+        let payload = cursor.take(len).map_err(corrupt)?;
+        let expected = cursor.u32().map_err(corrupt)?;
+        if crc32(payload) != expected {
+            return Err(corrupt("checksum mismatch".to_string()));
+        }
+        entries.push(decode_payload(payload).map_err(corrupt)?);
+    }
+    Ok(entries)
+}
+
+fn decode_payload(payload: &[u8]) -> Result<JournalEntry, String> {
+    let mut cursor = Cursor { bytes: payload, pos: 0 };
+    let sequence = cursor.u64()?;
+    let timestamp = UNIX_EPOCH + Duration::from_nanos(cursor.u64()?);
+    let state = *StateKind::ALL.get(cursor.u8()? as usize).ok_or("unknown state")?;
+    let event = cursor.text()?;
+    let current_var = cursor.f64()?;
+    let current_es = match cursor.u8()? {
+        0 => None,
+        _ => Some(cursor.f64()?),
+    };
+    let var_limit = cursor.f64()?;
+    let warning_level = cursor.f64()?;
+    let mut details = BTreeMap::new();
+    for _ in 0..cursor.u32()? {
+        let key = cursor.text()?;
+        let value = match cursor.u8()? {
+            FIELD_TEXT => Field::Text(cursor.text()?),
+            FIELD_NUMBER => Field::Number(cursor.f64()?),
+            FIELD_INTEGER => Field::Integer(cursor.u64()?),
+            tag => return Err(format!("unknown field tag {}", tag)),
+        };
+        details.insert(key, value);
+    }
+    let mut positions = BTreeMap::new();
+    for _ in 0..cursor.u32()? {
+        let id = cursor.text()?;
+        positions.insert(id, cursor.f64()?);
+    }
+    Ok(JournalEntry {
+        sequence,
+        timestamp,
+        state,
+        event,
+        current_var,
+        current_es,
+        var_limit,
+        warning_level,
+        details,
+        positions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::{MockClock, RiskManager};
+    use std::sync::mpsc;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("risk_journal_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_journal_round_trip_and_queries() {
+        for format in [JournalFormat::JsonLines, JournalFormat::Binary] {
+            let path = journal_path(&format!("{:?}", format));
+            let (sender, _receiver) = mpsc::channel();
+            let clock = MockClock::default();
+            let mut risk_manager = RiskManager::new(100.0, 80.0, sender).with_clock(Box::new(clock.clone()));
+            let subscription = risk_manager.subscribe(Box::new(JournalWriter::open(&path, format).unwrap()));
+
+            risk_manager.add_position("Position \"1\"", 50.0);
+            clock.advance(Duration::from_secs(60));
+            let breach_time = risk_manager.now();
+            risk_manager.add_position("Position2", 55.0);
+            clock.advance(Duration::from_secs(60));
+            risk_manager.remove_position("Position2");
+
+            let journal = JournalReader::open(&path, format).unwrap();
+            let sequences: Vec<u64> = journal.entries().iter().map(|entry| entry.sequence).collect();
+            assert_eq!(sequences, (0..journal.entries().len() as u64).collect::<Vec<_>>());
+
+            let breaches = journal.query(&JournalQuery::new().state(StateKind::LimitBreach).event("StateEntered"));
+            assert_eq!(breaches.len(), 1);
+            assert_eq!(breaches[0].timestamp, breach_time);
+            assert_eq!(breaches[0].current_var, 105.0);
+            assert_eq!(breaches[0].detail("from"), Some(&Field::Text("Normal".to_string())));
+            assert_eq!(
+                breaches[0].positions,
+                BTreeMap::from([("Position \"1\"".to_string(), 50.0), ("Position2".to_string(), 55.0)])
+            );
+            assert!(journal.in_state(StateKind::LimitBreach).iter().any(|entry| entry.event == "CommandSent"));
+            let last_minute = journal.between(breach_time + Duration::from_secs(1), breach_time + Duration::from_secs(3600));
+            assert!(last_minute.iter().any(|entry| entry.event == "PositionRemoved"));
+            assert!(last_minute.iter().all(|entry| entry.timestamp > breach_time));
+
+            // Reopening continues the numbering.
+            risk_manager.unsubscribe(subscription);
+            let writer = JournalWriter::open(&path, format).unwrap();
+            risk_manager.subscribe(Box::new(writer));
+            risk_manager.tick();
+            risk_manager.add_position("Position3", 1.0);
+            let reopened = JournalReader::open(&path, format).unwrap();
+            assert_eq!(reopened.entries()[journal.entries().len()].sequence, journal.entries().len() as u64);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_corrupt_records_are_detected() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let (sender, _receiver) = mpsc::channel();
+        let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+        let path = journal_path("corrupt");
+        risk_manager.subscribe(Box::new(JournalWriter::open(&path, JournalFormat::Binary).unwrap()));
+        risk_manager.add_position("Position1", 10.0);
+        risk_manager.add_position("Position2", 10.0);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 0xFF;
+        let err = JournalReader::from_reader(&bytes[..], JournalFormat::Binary).unwrap_err();
+        assert!(matches!(err, JournalError::Corrupt { reason, .. } if reason == "checksum mismatch"));
+
+        let entry = JournalReader::open(&path, JournalFormat::Binary).unwrap().entries()[0].clone();
+        let line = encode_json_line(&entry).replace("Position1", "Position9");
+        let err = JournalReader::from_reader(line.as_bytes(), JournalFormat::JsonLines).unwrap_err();
+        assert!(matches!(err, JournalError::Corrupt { record: 0, .. }));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_last_record_is_truncated_on_reopen() {
+        for format in [JournalFormat::JsonLines, JournalFormat::Binary] {
+            let path = journal_path(&format!("torn_{:?}", format));
+            let (sender, _receiver) = mpsc::channel();
+            let mut risk_manager = RiskManager::new(100.0, 80.0, sender);
+            let subscription = risk_manager.subscribe(Box::new(JournalWriter::open(&path, format).unwrap()));
+            risk_manager.add_position("Position1", 10.0);
+            risk_manager.unsubscribe(subscription);
+            risk_manager.add_position("Position2", 10.0);
+
+            // A crash halfway through the next append.
+            let complete = std::fs::read(&path).unwrap();
+            let entry = JournalReader::open(&path, format).unwrap().entries()[0].clone();
+            let record = match format {
+                JournalFormat::JsonLines => encode_json_line(&entry).into_bytes(),
+                JournalFormat::Binary => encode_binary(&entry),
+            };
+            let mut torn = complete.clone();
+            torn.extend_from_slice(&record[..record.len() / 2]);
+            std::fs::write(&path, &torn).unwrap();
+            assert!(JournalReader::open(&path, format).is_err());
+
+            let writer = JournalWriter::open(&path, format).unwrap();
+            let entries = JournalReader::from_reader(&complete[..], format).unwrap().entries().len();
+            let cut = writer.torn_record().unwrap();
+            assert_eq!((cut.record, cut.offset, cut.discarded_bytes), (entries, complete.len() as u64, (record.len() / 2) as u64));
+            assert_eq!(std::fs::read(&path).unwrap(), complete);
+
+            // Attached after the book was built, the writer still records all of it on a transition.
+            risk_manager.subscribe(Box::new(writer));
+            risk_manager.add_position("Position3", 70.0);
+            let journal = JournalReader::open(&path, format).unwrap();
+            let sequences: Vec<u64> = journal.entries().iter().map(|entry| entry.sequence).collect();
+            assert_eq!(sequences, (0..journal.entries().len() as u64).collect::<Vec<_>>());
+            let entered = journal.query(&JournalQuery::new().event("StateEntered"));
+            assert_eq!(entered[0].positions.keys().collect::<Vec<_>>(), ["Position1", "Position2", "Position3"]);
+
+            // Damage before the last record is not a torn append.
+            let mut damaged = std::fs::read(&path).unwrap();
+            damaged[12] ^= 0x01;
+            std::fs::write(&path, &damaged).unwrap();
+            assert!(matches!(JournalWriter::open(&path, format), Err(JournalError::Corrupt { record: 0, .. })));
+            if format == JournalFormat::Binary {
+                // A length prefix pointing past the end must not truncate the records after it.
+                let mut damaged = std::fs::read(&path).unwrap();
+                damaged[12] ^= 0x01;
+                damaged[3] ^= 0x40;
+                std::fs::write(&path, &damaged).unwrap();
+                assert!(matches!(JournalWriter::open(&path, format), Err(JournalError::Corrupt { record: 0, .. })));
+                assert_eq!(std::fs::read(&path).unwrap(), damaged);
+            }
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
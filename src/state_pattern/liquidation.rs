@@ -0,0 +1,287 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::var_engine::{RiskMeasures, VarEngine, VarError};
+
+/// Reduce `position_id` by `quantity`, i.e. its new size is `size - quantity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiquidationOrder {
+    pub position_id: String,
+    pub quantity: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiquidationPlan {
+    pub orders: Vec<LiquidationOrder>,
+    pub projected_var: f64,
+    pub target_var: f64,
+}
+
+impl LiquidationPlan {
+    pub fn reaches_target(&self) -> bool {
+        self.projected_var < self.target_var
+    }
+}
+
+/// Proposes which positions to cut, and by how much, to bring VaR below `target_var`.
+pub trait LiquidationStrategy: fmt::Debug {
+    fn plan(
+        &self,
+        engine: &dyn VarEngine,
+        positions: &HashMap<String, f64>,
+        target_var: f64,
+    ) -> Result<LiquidationPlan, VarError>;
+}
+
+/// Repeatedly cuts the largest remaining component VaR: fully while that is not enough, then partially.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedyLiquidation;
+
+impl LiquidationStrategy for GreedyLiquidation {
+    fn plan(
+        &self,
+        engine: &dyn VarEngine,
+        positions: &HashMap<String, f64>,
+        target_var: f64,
+    ) -> Result<LiquidationPlan, VarError> {
+        let mut book = positions.clone();
+        let mut orders = Vec::new();
+        let mut projected_var = engine.measure(&book)?.var;
+        while projected_var >= target_var {
+            let attribution = engine.attribute(&book)?;
+            let largest = match attribution.positions.iter().find(|p| p.component_var > 0.0 && p.size != 0.0) {
+                Some(position) => position.position_id.clone(),
+                None => break,
+            };
+            let reduce = vec![largest.clone()];
+            let fraction = smallest_sufficient_cut(engine, &book, &reduce, target_var)?.unwrap_or(1.0);
+            let size = book[&largest];
+            orders.push(LiquidationOrder { position_id: largest.clone(), quantity: size * fraction });
+            book.insert(largest, size * (1.0 - fraction));
+            projected_var = engine.measure(&book)?.var;
+        }
+        Ok(LiquidationPlan { orders, projected_var, target_var })
+    }
+}
+
+/// Searches for the fewest positions that need to be touched, then cuts them proportionally,
+/// preferring the subset that needs the least size to be reduced.
+///
+/// The search is exhaustive over the positions adding to VaR, so books with more of them
+/// than `max_positions` are handed to `GreedyLiquidation` instead. Each subset tried costs up to
+/// a hundred or so `measure` calls, made synchronously on entering LimitBreach; once
+/// `measure_budget` calls are spent the search also gives up and cuts greedily. Keep the budget
+/// small with slow engines such as `MonteCarloVar`.
+#[derive(Debug, Clone, Copy)]
+pub struct OptimisedLiquidation {
+    max_positions: usize,
+    measure_budget: usize,
+}
+
+/// Most positions `OptimisedLiquidation` searches exhaustively, i.e. 2^20 subsets.
+pub const MAX_SEARCHED_POSITIONS: usize = 20;
+
+impl OptimisedLiquidation {
+    pub fn new(max_positions: usize) -> Result<Self, VarError> {
+        if max_positions > MAX_SEARCHED_POSITIONS {
+            return Err(VarError::InvalidParameter(format!(
+                "at most {} positions can be searched, not {}",
+                MAX_SEARCHED_POSITIONS, max_positions
+            )));
+        }
+        Ok(OptimisedLiquidation { max_positions, ..OptimisedLiquidation::default() })
+    }
+
+    pub fn with_measure_budget(mut self, measure_budget: usize) -> Self {
+        self.measure_budget = measure_budget;
+        self
+    }
+
+    pub fn max_positions(&self) -> usize {
+        self.max_positions
+    }
+}
+
+impl Default for OptimisedLiquidation {
+    fn default() -> Self {
+        OptimisedLiquidation { max_positions: 12, measure_budget: 10_000 }
+    }
+}
+
+/// Counts the `measure` calls made through it, for `OptimisedLiquidation::measure_budget`.
+#[derive(Debug)]
+struct CountedEngine<'a> {
+    engine: &'a dyn VarEngine,
+    calls: Cell<usize>,
+}
+
+impl VarEngine for CountedEngine<'_> {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError> {
+        self.calls.set(self.calls.get() + 1);
+        self.engine.measure(positions)
+    }
+}
+
+impl LiquidationStrategy for OptimisedLiquidation {
+    fn plan(
+        &self,
+        engine: &dyn VarEngine,
+        positions: &HashMap<String, f64>,
+        target_var: f64,
+    ) -> Result<LiquidationPlan, VarError> {
+        let current_var = engine.measure(positions)?.var;
+        if current_var < target_var {
+            return Ok(LiquidationPlan { orders: Vec::new(), projected_var: current_var, target_var });
+        }
+        let candidates: Vec<String> = engine
+            .attribute(positions)?
+            .positions
+            .into_iter()
+            .filter(|p| p.component_var > 0.0 && p.size != 0.0)
+            .map(|p| p.position_id)
+            .collect();
+        if candidates.len() > self.max_positions {
+            return GreedyLiquidation.plan(engine, positions, target_var);
+        }
+
+        let counted = CountedEngine { engine, calls: Cell::new(0) };
+        for count in 1..=candidates.len() {
+            let mut best: Option<(f64, Vec<String>, f64)> = None;
+            // At most MAX_SEARCHED_POSITIONS candidates, so the shift cannot overflow.
+            for mask in 0usize..(1 << candidates.len()) {
+                if mask.count_ones() as usize != count {
+                    continue;
+                }
+                let subset: Vec<String> = candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| mask & (1 << idx) != 0)
+                    .map(|(_, id)| id.clone())
+                    .collect();
+                if counted.calls.get() >= self.measure_budget {
+                    return GreedyLiquidation.plan(engine, positions, target_var);
+                }
+                if let Some(fraction) = smallest_sufficient_cut(&counted, positions, &subset, target_var)? {
+                    let reduced: f64 = subset.iter().map(|id| (positions[id] * fraction).abs()).sum();
+                    if best.as_ref().is_none_or(|(least, _, _)| reduced < *least) {
+                        best = Some((reduced, subset, fraction));
+                    }
+                }
+            }
+            if let Some((_, subset, fraction)) = best {
+                let mut book = positions.clone();
+                let mut orders = Vec::with_capacity(subset.len());
+                for id in subset {
+                    let size = positions[&id];
+                    book.insert(id.clone(), size * (1.0 - fraction));
+                    orders.push(LiquidationOrder { position_id: id, quantity: size * fraction });
+                }
+                let projected_var = engine.measure(&book)?.var;
+                return Ok(LiquidationPlan { orders, projected_var, target_var });
+            }
+        }
+        // Even closing everything that adds risk is not enough: do the best we can.
+        GreedyLiquidation.plan(engine, positions, target_var)
+    }
+}
+
+/// Smallest fraction by which cutting every position in `subset` brings VaR below the target,
+/// or `None` if no cut of them is enough.
+///
+/// VaR is not monotonic in the cut once a position starts to act as a hedge, so the first
+/// sufficient fraction is located on a grid and then refined by bisection.
+fn smallest_sufficient_cut(
+    engine: &dyn VarEngine,
+    positions: &HashMap<String, f64>,
+    subset: &[String],
+    target_var: f64,
+) -> Result<Option<f64>, VarError> {
+    let var_after_cut = |fraction: f64| -> Result<f64, VarError> {
+        let mut book = positions.clone();
+        for id in subset {
+            let size = positions[id];
+            book.insert(id.clone(), size * (1.0 - fraction));
+        }
+        Ok(engine.measure(&book)?.var)
+    };
+    const GRID: usize = 64;
+    let mut bracket = None;
+    for step in 1..=GRID {
+        let fraction = step as f64 / GRID as f64;
+        if var_after_cut(fraction)? < target_var {
+            bracket = Some(((step - 1) as f64 / GRID as f64, fraction));
+            break;
+        }
+    }
+    let (mut low, mut high) = match bracket {
+        Some(bracket) => bracket,
+        None => return Ok(None),
+    };
+    for _ in 0..50 {
+        let mid = 0.5 * (low + high);
+        if var_after_cut(mid)? < target_var {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    Ok(Some(high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::var_engine::{CovarianceMatrix, ParametricVar, SummedVar};
+
+    #[test]
+    fn test_greedy_cuts_largest_contributors_first() {
+        let positions = HashMap::from([
+            ("A".to_string(), 50.0),
+            ("B".to_string(), 48.0),
+            ("C".to_string(), 47.0),
+        ]);
+        let plan = GreedyLiquidation.plan(&SummedVar, &positions, 80.0).unwrap();
+        assert!(plan.reaches_target());
+        assert_eq!(plan.orders.len(), 2);
+        assert_eq!(plan.orders[0], LiquidationOrder { position_id: "A".to_string(), quantity: 50.0 });
+        assert_eq!(plan.orders[1].position_id, "B");
+        assert!((plan.orders[1].quantity - 15.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_optimised_touches_fewest_positions_and_never_cuts_hedges() {
+        let covariance = CovarianceMatrix::from_correlation(
+            &["rates", "equity"],
+            &[1.0, 1.0],
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+        )
+        .unwrap();
+        let engine = ParametricVar::new(0.99, covariance)
+            .unwrap()
+            .with_exposures("Bond", &[("rates", 1.0)])
+            .with_exposures("Stock", &[("equity", 1.0)])
+            .with_exposures("Hedge", &[("equity", -1.0)]);
+        let positions = HashMap::from([
+            ("Bond".to_string(), 30.0),
+            ("Stock".to_string(), 60.0),
+            ("Hedge".to_string(), 20.0),
+        ]);
+        // Net equity 40 and rates 30: VaR = z * 50. Cutting only the stock can get below z * 35.
+        let target = 35.0 * 2.326_347_874;
+
+        let plan = OptimisedLiquidation::default().plan(&engine, &positions, target).unwrap();
+        assert!(plan.reaches_target());
+        assert_eq!(plan.orders.len(), 1);
+        assert_eq!(plan.orders[0].position_id, "Stock");
+        let greedy = GreedyLiquidation.plan(&engine, &positions, target).unwrap();
+        assert!(greedy.reaches_target());
+        assert!(greedy.orders.iter().all(|order| order.position_id != "Hedge"));
+
+        // Out of budget, the search settles for the greedy plan.
+        let budgeted = OptimisedLiquidation::default().with_measure_budget(1).plan(&engine, &positions, target).unwrap();
+        assert_eq!(budgeted, greedy);
+        assert!(OptimisedLiquidation::new(MAX_SEARCHED_POSITIONS).is_ok());
+        assert!(matches!(OptimisedLiquidation::new(32), Err(VarError::InvalidParameter(_))));
+    }
+}
@@ -0,0 +1,864 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::ops::{BitAnd, BitOr, Not};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::config::{parse_severity, parse_toml, ConfigError, Table};
+use super::notifier::Severity;
+use super::{RiskManager, RiskState, StateKind, TradingEngineCommand};
+
+/// A condition on the current `Situation`, combined with `!`, `&` and `|`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Guard {
+    Always,
+    AtWarning,
+    AtLimit,
+    /// A metric is at its limit times the shutdown multiplier.
+    AtShutdown,
+    BelowWarningExit,
+    BelowLimitExit,
+    EscalationDue,
+    EngineAlive,
+    /// LimitBreach was reached by a Warning timing out.
+    Escalated,
+    /// EngineUnavailable was entered from this state.
+    ResumesTo(StateKind),
+    /// Taken by the watchdog, not by `check_state`, where it never holds.
+    EngineLost,
+    /// Taken by `RiskManager::reset_shutdown`, not by `check_state`, where it never holds.
+    OperatorReset,
+    Not(Box<Guard>),
+    All(Vec<Guard>),
+    Any(Vec<Guard>),
+}
+
+impl Guard {
+    pub fn eval(&self, situation: &Situation) -> bool {
+        match self {
+            Guard::Always => true,
+            Guard::AtWarning => situation.at_warning,
+            Guard::AtLimit => situation.at_limit,
+            Guard::AtShutdown => situation.at_shutdown,
+            Guard::BelowWarningExit => situation.below_warning_exit,
+            Guard::BelowLimitExit => situation.below_limit_exit,
+            Guard::EscalationDue => situation.escalation_due,
+            Guard::EngineAlive => situation.engine_alive,
+            Guard::Escalated => situation.escalated,
+            Guard::ResumesTo(state) => situation.resume == Some(*state),
+            Guard::EngineLost | Guard::OperatorReset => false,
+            Guard::Not(guard) => !guard.eval(situation),
+            Guard::All(guards) => guards.iter().all(|guard| guard.eval(situation)),
+            Guard::Any(guards) => guards.iter().any(|guard| guard.eval(situation)),
+        }
+    }
+
+    /// Whether the transition is taken outside `check_state`.
+    pub fn is_external(&self) -> bool {
+        matches!(self, Guard::EngineLost | Guard::OperatorReset)
+    }
+}
+
+impl Not for Guard {
+    type Output = Guard;
+
+    fn not(self) -> Guard {
+        match self {
+            Guard::Not(guard) => *guard,
+            guard => Guard::Not(Box::new(guard)),
+        }
+    }
+}
+
+impl BitAnd for Guard {
+    type Output = Guard;
+
+    fn bitand(self, other: Guard) -> Guard {
+        match (self, other) {
+            (Guard::All(mut guards), Guard::All(others)) => {
+                guards.extend(others);
+                Guard::All(guards)
+            }
+            (Guard::All(mut guards), other) => {
+                guards.push(other);
+                Guard::All(guards)
+            }
+            (guard, other) => Guard::All(vec![guard, other]),
+        }
+    }
+}
+
+impl BitOr for Guard {
+    type Output = Guard;
+
+    fn bitor(self, other: Guard) -> Guard {
+        match (self, other) {
+            (Guard::Any(mut guards), Guard::Any(others)) => {
+                guards.extend(others);
+                Guard::Any(guards)
+            }
+            (Guard::Any(mut guards), other) => {
+                guards.push(other);
+                Guard::Any(guards)
+            }
+            (guard, other) => Guard::Any(vec![guard, other]),
+        }
+    }
+}
+
+/// Renders the guard in the syntax `from_str` reads, e.g. `AtLimit and not AtShutdown`.
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, guards: &[Guard], separator: &str, empty: &str| {
+            if guards.is_empty() {
+                return write!(f, "{}", empty);
+            }
+            for (idx, guard) in guards.iter().enumerate() {
+                if idx > 0 {
+                    write!(f, " {} ", separator)?;
+                }
+                match guard {
+                    Guard::All(_) if separator == "and" => write!(f, "({})", guard)?,
+                    Guard::Any(_) => write!(f, "({})", guard)?,
+                    _ => write!(f, "{}", guard)?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Guard::Not(guard) => match **guard {
+                Guard::All(_) | Guard::Any(_) => write!(f, "not ({})", guard),
+                _ => write!(f, "not {}", guard),
+            },
+            Guard::All(guards) => join(f, guards, "and", "Always"),
+            Guard::Any(guards) => join(f, guards, "or", "not Always"),
+            Guard::ResumesTo(state) => write!(f, "ResumesTo{:?}", state),
+            guard => write!(f, "{:?}", guard),
+        }
+    }
+}
+
+impl FromStr for Guard {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Guard, String> {
+        let spaced = text.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens: VecDeque<&str> = spaced.split_whitespace().collect();
+        let guard = parse_any(&mut tokens)?;
+        match tokens.front() {
+            None => Ok(guard),
+            Some(token) => Err(format!("unexpected {} in guard {}", token, text)),
+        }
+    }
+}
+
+fn parse_any(tokens: &mut VecDeque<&str>) -> Result<Guard, String> {
+    let mut guard = parse_all(tokens)?;
+    while tokens.front() == Some(&"or") {
+        tokens.pop_front();
+        guard = guard | parse_all(tokens)?;
+    }
+    Ok(guard)
+}
+
+fn parse_all(tokens: &mut VecDeque<&str>) -> Result<Guard, String> {
+    let mut guard = parse_unary(tokens)?;
+    while tokens.front() == Some(&"and") {
+        tokens.pop_front();
+        guard = guard & parse_unary(tokens)?;
+    }
+    Ok(guard)
+}
+
+fn parse_unary(tokens: &mut VecDeque<&str>) -> Result<Guard, String> {
+    match tokens.pop_front() {
+        Some("not") => Ok(!parse_unary(tokens)?),
+        Some("(") => {
+            let inner = parse_any(tokens)?;
+            match tokens.pop_front() {
+                Some(")") => Ok(inner),
+                _ => Err("missing )".to_string()),
+            }
+        }
+        Some(name) => match name {
+            "Always" => Ok(Guard::Always),
+            "AtWarning" => Ok(Guard::AtWarning),
+            "AtLimit" => Ok(Guard::AtLimit),
+            "AtShutdown" => Ok(Guard::AtShutdown),
+            "BelowWarningExit" => Ok(Guard::BelowWarningExit),
+            "BelowLimitExit" => Ok(Guard::BelowLimitExit),
+            "EscalationDue" => Ok(Guard::EscalationDue),
+            "EngineAlive" => Ok(Guard::EngineAlive),
+            "Escalated" => Ok(Guard::Escalated),
+            "EngineLost" => Ok(Guard::EngineLost),
+            "OperatorReset" => Ok(Guard::OperatorReset),
+            _ => match name.strip_prefix("ResumesTo") {
+                Some(state) => state.parse().map(Guard::ResumesTo),
+                None => Err(format!("unknown guard {}", name)),
+            },
+        },
+        None => Err("guard ends too early".to_string()),
+    }
+}
+
+/// What guards are evaluated against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Situation {
+    pub at_warning: bool,
+    pub at_limit: bool,
+    pub at_shutdown: bool,
+    pub below_warning_exit: bool,
+    pub below_limit_exit: bool,
+    pub escalation_due: bool,
+    pub engine_alive: bool,
+    pub escalated: bool,
+    pub resume: Option<StateKind>,
+}
+
+impl Situation {
+    pub(crate) fn of(context: &RiskManager, state: &dyn RiskState) -> Self {
+        Situation {
+            at_warning: context.at_warning(),
+            at_limit: context.at_limit(),
+            at_shutdown: context.should_shutdown(),
+            below_warning_exit: context.below_warning_exit(),
+            below_limit_exit: context.below_limit_exit(),
+            escalation_due: context.escalation_due(),
+            engine_alive: context.engine_alive(),
+            escalated: state.escalated(),
+            resume: state.resumes_to(),
+        }
+    }
+
+    /// Every situation `state` can be in, assuming warning exit <= warning <= limit exit <= limit.
+    fn possible(state: StateKind) -> Vec<Situation> {
+        // Risk from below the warning exit level up to the shutdown level, as
+        // (at_warning, at_limit, at_shutdown, below_warning_exit, below_limit_exit).
+        let levels = [
+            (false, false, false, true, true),
+            (false, false, false, false, true),
+            (true, false, false, false, true),
+            (true, false, false, false, false),
+            (true, true, false, false, false),
+            (true, true, true, false, false),
+        ];
+        let escalated: &[bool] = if state == StateKind::LimitBreach { &[false, true] } else { &[false] };
+        let resume: Vec<Option<StateKind>> = match state {
+            StateKind::EngineUnavailable => StateKind::ALL.iter().copied().map(Some).collect(),
+            _ => vec![None],
+        };
+        let mut situations = Vec::new();
+        for (at_warning, at_limit, at_shutdown, below_warning_exit, below_limit_exit) in levels {
+            for escalation_due in [false, true] {
+                for engine_alive in [true, false] {
+                    for &escalated in escalated {
+                        for &resume in &resume {
+                            situations.push(Situation {
+                                at_warning,
+                                at_limit,
+                                at_shutdown,
+                                below_warning_exit,
+                                below_limit_exit,
+                                escalation_due,
+                                engine_alive,
+                                escalated,
+                                resume,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        situations
+    }
+}
+
+impl fmt::Display for Situation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let facts = [
+            (self.at_warning, "AtWarning"),
+            (self.at_limit, "AtLimit"),
+            (self.at_shutdown, "AtShutdown"),
+            (self.below_warning_exit, "BelowWarningExit"),
+            (self.below_limit_exit, "BelowLimitExit"),
+            (self.escalation_due, "EscalationDue"),
+            (self.engine_alive, "EngineAlive"),
+            (self.escalated, "Escalated"),
+        ];
+        let mut facts: Vec<String> = facts.iter().filter(|(holds, _)| *holds).map(|(_, name)| name.to_string()).collect();
+        if let Some(state) = self.resume {
+            facts.push(format!("ResumesTo{:?}", state));
+        }
+        if facts.is_empty() {
+            write!(f, "nothing holds")
+        } else {
+            write!(f, "{}", facts.join(", "))
+        }
+    }
+}
+
+/// The command a state keeps sending to the engines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StandingCommand {
+    Fixed(TradingEngineCommand),
+    /// `TradingEngineCommand::Throttle` with the manager's current throttle limits.
+    Throttle,
+}
+
+impl StandingCommand {
+    pub fn resolve(&self, context: &RiskManager) -> TradingEngineCommand {
+        match self {
+            StandingCommand::Fixed(command) => command.clone(),
+            StandingCommand::Throttle => TradingEngineCommand::Throttle(context.throttle),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// `{resume}` in the message is replaced by the state EngineUnavailable was entered from.
+    Notify { severity: Severity, subject: String, message: String, with_contributors: bool },
+    Dispatch(TradingEngineCommand),
+    /// Sends the liquidation plan, if it has any orders.
+    Liquidate,
+    /// Sends the state's standing command.
+    SendCommand,
+}
+
+impl Action {
+    pub fn notify(severity: Severity, subject: &str, message: &str) -> Self {
+        Action::Notify { severity, subject: subject.to_string(), message: message.to_string(), with_contributors: false }
+    }
+
+    /// A notification with the largest VaR contributors appended, so the recipients know what to cut.
+    pub fn notify_with_contributors(severity: Severity, subject: &str, message: &str) -> Self {
+        Action::Notify { severity, subject: subject.to_string(), message: message.to_string(), with_contributors: true }
+    }
+
+    pub(crate) fn run(&self, spec: &StateSpec, state: &dyn RiskState, context: &RiskManager) {
+        match self {
+            Action::Notify { severity, subject, message, with_contributors } => {
+                let resume = state.resumes_to().map_or(String::new(), |resume| resume.to_string());
+                let message = message.replace("{resume}", &resume);
+                let message = if *with_contributors { context.with_top_contributors(&message) } else { message };
+                context.notify(*severity, subject, &message);
+            }
+            Action::Dispatch(command) => context.dispatch(command.clone()),
+            Action::Liquidate => match context.liquidation_plan() {
+                Ok(plan) if !plan.orders.is_empty() => context.dispatch(TradingEngineCommand::Liquidate(plan.orders)),
+                Ok(_) => (),
+                Err(err) => context.notify(Severity::Critical, "Liquidation plan failed", &err.to_string()),
+            },
+            Action::SendCommand => context.dispatch(spec.command.resolve(context)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSpec {
+    pub kind: StateKind,
+    pub command: StandingCommand,
+    /// Staying put is right while this holds; the validator reports situations where it does
+    /// not and no transition fires.
+    pub invariant: Guard,
+    pub on_entry: Vec<Action>,
+    pub on_exit: Vec<Action>,
+}
+
+impl StateSpec {
+    pub fn new(kind: StateKind, command: StandingCommand) -> Self {
+        StateSpec { kind, command, invariant: Guard::Always, on_entry: Vec::new(), on_exit: Vec::new() }
+    }
+
+    pub fn with_invariant(mut self, invariant: Guard) -> Self {
+        self.invariant = invariant;
+        self
+    }
+
+    pub fn with_entry_action(mut self, action: Action) -> Self {
+        self.on_entry.push(action);
+        self
+    }
+
+    pub fn with_exit_action(mut self, action: Action) -> Self {
+        self.on_exit.push(action);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionRule {
+    pub from: StateKind,
+    pub to: StateKind,
+    pub guard: Guard,
+    /// Marks the state entered as escalated, see `Guard::Escalated`.
+    pub escalates: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MachineIssue {
+    UndeclaredState(StateKind),
+    Unreachable(StateKind),
+    /// The state has no transitions out of it at all.
+    DeadEnd(StateKind),
+    /// Two transitions out of `from` can fire in the same situation.
+    Overlap { from: StateKind, first: StateKind, second: StateKind, situation: Situation },
+    /// The state's invariant no longer holds, yet no transition fires.
+    Missing { state: StateKind, situation: Situation },
+}
+
+impl fmt::Display for MachineIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineIssue::UndeclaredState(state) => write!(f, "{} is not declared", state),
+            MachineIssue::Unreachable(state) => write!(f, "{} cannot be reached from {}", state, StateKind::Normal),
+            MachineIssue::DeadEnd(state) => write!(f, "{} has no transitions out of it", state),
+            MachineIssue::Overlap { from, first, second, situation } => write!(
+                f,
+                "from {} both the transition to {} and the one to {} fire when {}",
+                from, first, second, situation
+            ),
+            MachineIssue::Missing { state, situation } => {
+                write!(f, "{} should be left but no transition fires when {}", state, situation)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MachineError {
+    Config(ConfigError),
+    Invalid(Vec<MachineIssue>),
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::Config(err) => write!(f, "{}", err),
+            MachineError::Invalid(issues) => {
+                write!(f, "{} problems in the state machine:", issues.len())?;
+                for issue in issues {
+                    write!(f, " [{}]", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+impl From<ConfigError> for MachineError {
+    fn from(err: ConfigError) -> Self {
+        MachineError::Config(err)
+    }
+}
+
+/// The risk states, what they do on entry and exit, and the guarded transitions between them.
+///
+/// `check_state` takes the first declared transition out of the current state whose guard
+/// holds; guards out of one state are expected not to overlap, which `validate` checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMachine {
+    states: BTreeMap<StateKind, StateSpec>,
+    transitions: Vec<TransitionRule>,
+}
+
+impl StateMachine {
+    pub fn builder() -> StateMachineBuilder {
+        StateMachineBuilder { machine: StateMachine { states: BTreeMap::new(), transitions: Vec::new() }, last_state: None }
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = &StateSpec> {
+        self.states.values()
+    }
+
+    pub fn spec(&self, state: StateKind) -> Option<&StateSpec> {
+        self.states.get(&state)
+    }
+
+    pub fn transitions(&self) -> &[TransitionRule] {
+        &self.transitions
+    }
+
+    /// The first rule that fires, with its index in `transitions`.
+    pub(crate) fn next(&self, state: &dyn RiskState, context: &RiskManager) -> Option<usize> {
+        let situation = Situation::of(context, state);
+        self.transitions.iter().position(|rule| rule.from == state.kind() && rule.guard.eval(&situation))
+    }
+
+    /// The rule taking an external event (`EngineLost`, `OperatorReset`) out of `from`, if the machine declares one.
+    pub(crate) fn external(&self, from: StateKind, guard: &Guard) -> Option<usize> {
+        self.transitions.iter().position(|rule| rule.from == from && rule.guard == *guard)
+    }
+
+    fn firing(&self, from: StateKind, situation: Situation) -> impl Iterator<Item = &TransitionRule> + '_ {
+        self.transitions.iter().filter(move |rule| rule.from == from && rule.guard.eval(&situation))
+    }
+
+    /// Checks for undeclared, unreachable and dead-end states, and, in every situation a state
+    /// can be in, for overlapping guards and for missing transitions.
+    pub fn validate(&self) -> Vec<MachineIssue> {
+        let mut issues = Vec::new();
+        for state in StateKind::ALL {
+            if !self.states.contains_key(&state) {
+                issues.push(MachineIssue::UndeclaredState(state));
+            }
+        }
+
+        let mut reached = BTreeSet::from([StateKind::Normal]);
+        let mut pending = vec![StateKind::Normal];
+        while let Some(from) = pending.pop() {
+            for rule in self.transitions.iter().filter(|rule| rule.from == from) {
+                if reached.insert(rule.to) {
+                    pending.push(rule.to);
+                }
+            }
+        }
+        for state in self.states.keys() {
+            if !reached.contains(state) {
+                issues.push(MachineIssue::Unreachable(*state));
+            }
+            if !self.transitions.iter().any(|rule| rule.from == *state) {
+                issues.push(MachineIssue::DeadEnd(*state));
+            }
+        }
+
+        for (state, spec) in &self.states {
+            let mut overlaps = BTreeSet::new();
+            for situation in Situation::possible(*state) {
+                let firing: Vec<&TransitionRule> = self.firing(*state, situation).collect();
+                if firing.is_empty() && !spec.invariant.eval(&situation) {
+                    issues.push(MachineIssue::Missing { state: *state, situation });
+                }
+                for (idx, first) in firing.iter().enumerate() {
+                    for second in &firing[idx + 1..] {
+                        let same = first.to == second.to && first.escalates == second.escalates;
+                        // One example per pair is enough to point at the guards.
+                        if !same && overlaps.insert((first.to, second.to)) {
+                            issues.push(MachineIssue::Overlap { from: *state, first: first.to, second: second.to, situation });
+                        }
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MachineError> {
+        Self::parse(&fs::read_to_string(path).map_err(ConfigError::from)?)
+    }
+
+    /// Reads a machine from TOML and validates it:
+    ///
+    /// ```toml
+    /// [[state]]
+    /// kind = "LimitBreach"
+    /// command = "NoTrade"        # or Throttle, for the manager's throttle limits
+    /// invariant = "not BelowLimitExit and not AtShutdown and not EscalationDue"
+    ///
+    /// [[state.on_entry]]         # also [[state.on_exit]]; applies to the last [[state]]
+    /// action = "notify"          # notify, dispatch, liquidate or send_command
+    /// severity = "critical"
+    /// subject = "Limit Breach"
+    /// message = "New trades are blocked."
+    /// with_contributors = true
+    ///
+    /// [[transition]]
+    /// from = "Warning"
+    /// to = "LimitBreach"
+    /// when = "EscalationDue and not AtLimit"
+    /// escalates = true
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, MachineError> {
+        let mut builder = StateMachine::builder();
+        for mut table in parse_toml(text)? {
+            let name = table.name.clone();
+            match (name.as_str(), table.array) {
+                ("state", true) => {
+                    let kind = table.parse("kind", StateKind::from_str)?.ok_or_else(|| table.missing("kind"))?;
+                    let command = table.parse("command", parse_standing_command)?.ok_or_else(|| table.missing("command"))?;
+                    let mut spec = StateSpec::new(kind, command);
+                    if let Some(invariant) = table.parse("invariant", Guard::from_str)? {
+                        spec.invariant = invariant;
+                    }
+                    builder = builder.state(spec);
+                }
+                ("state.on_entry" | "state.on_exit", true) => {
+                    let action = parse_action(&mut table)?;
+                    let spec = match builder.last_state.and_then(|kind| builder.machine.states.get_mut(&kind)) {
+                        Some(spec) => spec,
+                        None => {
+                            let reason = format!("[[{}]] must follow a [[state]]", name);
+                            return Err(ConfigError::Parse { line: table.line, reason }.into());
+                        }
+                    };
+                    if name == "state.on_entry" {
+                        spec.on_entry.push(action);
+                    } else {
+                        spec.on_exit.push(action);
+                    }
+                }
+                ("transition", true) => {
+                    let from = table.parse("from", StateKind::from_str)?.ok_or_else(|| table.missing("from"))?;
+                    let to = table.parse("to", StateKind::from_str)?.ok_or_else(|| table.missing("to"))?;
+                    let guard = table.parse("when", Guard::from_str)?.ok_or_else(|| table.missing("when"))?;
+                    let escalates = table.flag("escalates")?.unwrap_or(false);
+                    builder.machine.transitions.push(TransitionRule { from, to, guard, escalates });
+                }
+                (name, _) => {
+                    let reason = format!("unknown table [{}]", name);
+                    return Err(ConfigError::Parse { line: table.line, reason }.into());
+                }
+            }
+            table.finish()?;
+        }
+        builder.build()
+    }
+}
+
+fn parse_command(name: &str) -> Result<TradingEngineCommand, String> {
+    match name {
+        "ExecuteTrade" => Ok(TradingEngineCommand::ExecuteTrade),
+        "NoTrade" => Ok(TradingEngineCommand::NoTrade),
+        "StopEngine" => Ok(TradingEngineCommand::StopEngine),
+        "ReduceOnly" => Ok(TradingEngineCommand::ReduceOnly),
+        "CancelAllOrders" => Ok(TradingEngineCommand::CancelAllOrders),
+        "Resume" => Ok(TradingEngineCommand::Resume),
+        _ => Err(format!("unknown command {}", name)),
+    }
+}
+
+fn parse_standing_command(name: &str) -> Result<StandingCommand, String> {
+    match name {
+        "Throttle" => Ok(StandingCommand::Throttle),
+        name => parse_command(name).map(StandingCommand::Fixed),
+    }
+}
+
+fn parse_action(table: &mut Table) -> Result<Action, ConfigError> {
+    let line = table.line;
+    match table.text("action")?.as_str() {
+        "notify" => Ok(Action::Notify {
+            severity: table.parse("severity", parse_severity)?.ok_or_else(|| table.missing("severity"))?,
+            subject: table.text("subject")?,
+            message: table.text("message")?,
+            with_contributors: table.flag("with_contributors")?.unwrap_or(false),
+        }),
+        "dispatch" => Ok(Action::Dispatch(table.parse("command", parse_command)?.ok_or_else(|| table.missing("command"))?)),
+        "liquidate" => Ok(Action::Liquidate),
+        "send_command" => Ok(Action::SendCommand),
+        other => Err(ConfigError::Parse { line, reason: format!("unknown action {}", other) }),
+    }
+}
+
+pub struct StateMachineBuilder {
+    machine: StateMachine,
+    last_state: Option<StateKind>,
+}
+
+impl StateMachineBuilder {
+    /// Declares a state, replacing any earlier declaration of the same kind.
+    pub fn state(mut self, spec: StateSpec) -> Self {
+        self.last_state = Some(spec.kind);
+        self.machine.states.insert(spec.kind, spec);
+        self
+    }
+
+    pub fn transition(mut self, from: StateKind, to: StateKind, guard: Guard) -> Self {
+        self.machine.transitions.push(TransitionRule { from, to, guard, escalates: false });
+        self
+    }
+
+    /// A transition that marks the state entered as escalated.
+    pub fn escalation(mut self, from: StateKind, to: StateKind, guard: Guard) -> Self {
+        self.machine.transitions.push(TransitionRule { from, to, guard, escalates: true });
+        self
+    }
+
+    /// The machine, if `validate` finds nothing wrong with it.
+    pub fn build(self) -> Result<StateMachine, MachineError> {
+        let issues = self.machine.validate();
+        if issues.is_empty() {
+            Ok(self.machine)
+        } else {
+            Err(MachineError::Invalid(issues))
+        }
+    }
+}
+
+/// The standard risk states. Any state can jump straight to Shutdown once a metric reaches
+/// its shutdown level.
+impl Default for StateMachine {
+    fn default() -> Self {
+        use Guard::*;
+        use StateKind::{EngineUnavailable as Unavailable, LimitBreach as Breach, Normal, Recovery, Shutdown, Warning};
+        let command = |command| StandingCommand::Fixed(command);
+        // LimitBreach steps down to Warning only if it was not reached by a Warning timing out.
+        let breach_to_warning = !Escalated & BelowLimitExit & !BelowWarningExit;
+        let resumes_to_shutdown = ResumesTo(Shutdown);
+
+        StateMachine::builder()
+            .state(
+                StateSpec::new(Normal, command(TradingEngineCommand::ExecuteTrade))
+                    .with_invariant(!AtWarning)
+                    .with_entry_action(Action::Dispatch(TradingEngineCommand::Resume)),
+            )
+            .state(
+                StateSpec::new(Warning, StandingCommand::Throttle)
+                    .with_invariant(!BelowWarningExit & !AtLimit & !EscalationDue)
+                    .with_entry_action(Action::notify(
+                        Severity::Warning,
+                        "Warning Level",
+                        "Warning Level reached. Increased monitoring and trading limitations are in effect.",
+                    )),
+            )
+            .state(
+                StateSpec::new(Breach, command(TradingEngineCommand::NoTrade))
+                    .with_invariant(!BelowWarningExit & !breach_to_warning.clone() & !AtShutdown & !EscalationDue)
+                    .with_entry_action(Action::notify_with_contributors(
+                        Severity::Critical,
+                        "Limit Breach",
+                        "Limit Breach! New trades are blocked. Positions may be closed.",
+                    ))
+                    .with_entry_action(Action::Dispatch(TradingEngineCommand::CancelAllOrders))
+                    .with_entry_action(Action::Liquidate),
+            )
+            .state(
+                StateSpec::new(Shutdown, command(TradingEngineCommand::StopEngine))
+                    .with_entry_action(Action::notify_with_contributors(
+                        Severity::Critical,
+                        "Shutdown",
+                        "Shutdown initiated due to extreme risk levels.",
+                    ))
+                    .with_entry_action(Action::Dispatch(TradingEngineCommand::CancelAllOrders))
+                    .with_entry_action(Action::SendCommand),
+            )
+            .state(
+                StateSpec::new(Recovery, command(TradingEngineCommand::ReduceOnly))
+                    .with_invariant(!AtLimit & !BelowWarningExit)
+                    .with_entry_action(Action::notify(
+                        Severity::Warning,
+                        "Recovery",
+                        "Shutdown reset by operator. Trading is restricted to reducing positions.",
+                    )),
+            )
+            .state(
+                StateSpec::new(Unavailable, command(TradingEngineCommand::StopEngine))
+                    .with_invariant(!EngineAlive)
+                    .with_entry_action(Action::notify(
+                        Severity::Critical,
+                        "Engine Unavailable",
+                        "Lost contact with the trading engine while in {resume}. Its orders can no longer be controlled.",
+                    )),
+            )
+            .transition(Normal, Shutdown, AtShutdown)
+            .transition(Normal, Breach, AtLimit & !AtShutdown)
+            .transition(Normal, Warning, AtWarning & !AtLimit)
+            .transition(Warning, Normal, BelowWarningExit)
+            .transition(Warning, Shutdown, AtShutdown)
+            .transition(Warning, Breach, AtLimit & !AtShutdown)
+            .escalation(Warning, Breach, EscalationDue & !AtLimit & !BelowWarningExit)
+            .transition(Breach, Warning, breach_to_warning.clone())
+            .transition(Breach, Normal, BelowWarningExit)
+            .transition(Breach, Shutdown, (AtShutdown | EscalationDue) & !BelowWarningExit & !breach_to_warning)
+            .transition(Shutdown, Recovery, OperatorReset)
+            .transition(Recovery, Shutdown, AtShutdown)
+            .transition(Recovery, Breach, AtLimit & !AtShutdown)
+            .transition(Recovery, Normal, BelowWarningExit)
+            // Once the engine is heard from again the state follows the risk, but a Shutdown
+            // still needs an operator reset.
+            .transition(Unavailable, Shutdown, EngineAlive & (resumes_to_shutdown.clone() | AtShutdown))
+            .transition(Unavailable, Breach, EngineAlive & !resumes_to_shutdown.clone() & AtLimit & !AtShutdown)
+            .transition(Unavailable, Warning, EngineAlive & !resumes_to_shutdown.clone() & AtWarning & !AtLimit)
+            .transition(Unavailable, Normal, EngineAlive & !resumes_to_shutdown & !AtWarning)
+            .transition(Normal, Unavailable, EngineLost)
+            .transition(Warning, Unavailable, EngineLost)
+            .transition(Breach, Unavailable, EngineLost)
+            .transition(Shutdown, Unavailable, EngineLost)
+            .transition(Recovery, Unavailable, EngineLost)
+            .build()
+            .expect("the standard state machine is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_machine_is_valid_and_guards_round_trip() {
+        let machine = StateMachine::default();
+        assert!(machine.validate().is_empty());
+        for rule in machine.transitions() {
+            assert_eq!(rule.guard.to_string().parse::<Guard>(), Ok(rule.guard.clone()), "{}", rule.guard);
+        }
+        assert_eq!(
+            (Guard::AtShutdown | Guard::EscalationDue) & !Guard::BelowWarningExit,
+            "(AtShutdown or EscalationDue) and not BelowWarningExit".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_validator_reports_broken_tables() {
+        // Normal has no way out once at the limit, Warning's escalation overlaps its move to
+        // LimitBreach, and nothing leads to Recovery.
+        let text = r#"
+            [[state]]
+            kind = "Normal"
+            command = "ExecuteTrade"
+            invariant = "not AtWarning"
+
+            [[state.on_entry]]
+            action = "dispatch"
+            command = "Resume"
+
+            [[state]]
+            kind = "Warning"
+            command = "Throttle"
+            invariant = "not BelowWarningExit and not AtLimit and not EscalationDue"
+
+            [[state]]
+            kind = "Recovery"
+            command = "ReduceOnly"
+
+            [[transition]]
+            from = "Normal"
+            to = "Warning"
+            when = "AtWarning and not AtLimit"
+
+            [[transition]]
+            from = "Warning"
+            to = "Normal"
+            when = "BelowWarningExit"
+
+            [[transition]]
+            from = "Warning"
+            to = "LimitBreach"
+            when = "AtLimit"
+
+            [[transition]]
+            from = "Warning"
+            to = "LimitBreach"
+            when = "EscalationDue"
+            escalates = true
+        "#;
+        let issues = match StateMachine::parse(text) {
+            Err(MachineError::Invalid(issues)) => issues,
+            other => panic!("expected validation issues, got {:?}", other),
+        };
+        let missing = |state: StateKind| {
+            issues.iter().any(|issue| matches!(issue, MachineIssue::Missing { state: s, .. } if *s == state))
+        };
+        assert!(issues.contains(&MachineIssue::UndeclaredState(StateKind::Shutdown)));
+        assert!(issues.contains(&MachineIssue::Unreachable(StateKind::Recovery)));
+        assert!(issues.contains(&MachineIssue::DeadEnd(StateKind::Recovery)));
+        assert!(missing(StateKind::Normal));
+        assert!(!missing(StateKind::Warning));
+        assert!(issues.iter().any(|issue| matches!(
+            issue,
+            MachineIssue::Overlap { from: StateKind::Warning, first: StateKind::LimitBreach, second: StateKind::LimitBreach, .. }
+        )));
+    }
+}
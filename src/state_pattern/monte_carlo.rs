@@ -0,0 +1,294 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use super::var_engine::{check_confidence, tail_measures, CovarianceMatrix, RiskMeasures, VarEngine, VarError};
+use crate::template_method_pattern::MCSimulation;
+
+/// P&L of one unit of a position when its risk factor moves from `start` to `end`.
+pub type Revaluation = Box<dyn Fn(f64, f64) -> f64>;
+
+/// Unit P&L of a linear instrument, e.g. `linear(1.0)` for one share.
+pub fn linear(multiplier: f64) -> Revaluation {
+    Box::new(move |start, end| multiplier * (end - start))
+}
+
+/// Unit P&L of a rates position with the given DV01 when the short rate moves.
+pub fn dv01(dv01: f64) -> Revaluation {
+    Box::new(move |start, end| -dv01 * (end - start) * 10_000.0)
+}
+
+/// Monte Carlo VaR: revalues the book over simulated scenarios of its risk factors.
+///
+/// Each factor is driven by one of the `MCSimulation` processes, configured with the risk
+/// horizon as maturity (e.g. `maturity: 1.0 / 252.0` for one day). The factors' shocks are
+/// independent unless `with_correlation` is given. Scenarios are drawn on first use and reused
+/// until `regenerate` is called, so that VaR only moves when the book does.
+pub struct MonteCarloVar {
+    confidence: f64,
+    num_scenarios: usize,
+    factors: Vec<(String, Box<dyn MCSimulation>)>,
+    cholesky: Option<Vec<Vec<f64>>>,
+    seed: Option<u64>,
+    positions: HashMap<String, (usize, Revaluation)>,
+    scenarios: OnceCell<Vec<Vec<(f64, f64)>>>,
+}
+
+impl MonteCarloVar {
+    pub fn new(confidence: f64, num_scenarios: usize) -> Result<Self, VarError> {
+        check_confidence(confidence)?;
+        if num_scenarios == 0 {
+            return Err(VarError::InvalidParameter("at least one scenario is required".to_string()));
+        }
+        Ok(MonteCarloVar {
+            confidence,
+            num_scenarios,
+            factors: Vec::new(),
+            cholesky: None,
+            seed: None,
+            positions: HashMap::new(),
+            scenarios: OnceCell::new(),
+        })
+    }
+
+    pub fn with_factor(mut self, name: &str, process: Box<dyn MCSimulation>) -> Self {
+        self.factors.push((name.to_string(), process));
+        self.scenarios = OnceCell::new();
+        self
+    }
+
+    /// Correlates the shocks of the factors added so far, in the order they were added. Adding a
+    /// factor afterwards makes `measure` fail until a matrix covering all factors is given.
+    pub fn with_correlation(mut self, correlation: Vec<Vec<f64>>) -> Result<Self, VarError> {
+        let names: Vec<&str> = self.factors.iter().map(|(name, _)| name.as_str()).collect();
+        if correlation.len() != names.len() || correlation.iter().any(|row| row.len() != names.len()) {
+            return Err(VarError::InvalidData(format!("correlation matrix must be {0}x{0}, one row per factor", names.len())));
+        }
+        if correlation.iter().enumerate().any(|(i, row)| row.get(i) != Some(&1.0)) {
+            return Err(VarError::InvalidData("correlation matrix must have a unit diagonal".to_string()));
+        }
+        CovarianceMatrix::new(&names, correlation.clone())?;
+        self.cholesky = Some(cholesky(&correlation));
+        self.scenarios = OnceCell::new();
+        Ok(self)
+    }
+
+    /// Draws scenarios from a fixed seed, so that every draw is reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self.scenarios = OnceCell::new();
+        self
+    }
+
+    pub fn with_position(mut self, position_id: &str, factor: &str, revaluation: Revaluation) -> Result<Self, VarError> {
+        let idx = self
+            .factors
+            .iter()
+            .position(|(name, _)| name == factor)
+            .ok_or_else(|| VarError::UnknownFactor(factor.to_string()))?;
+        self.positions.insert(position_id.to_string(), (idx, revaluation));
+        Ok(self)
+    }
+
+    /// Discards the cached scenarios; the next measurement draws a fresh set.
+    pub fn regenerate(&mut self) {
+        self.scenarios = OnceCell::new();
+    }
+
+    /// Start and end value of every factor, per scenario.
+    fn scenarios(&self) -> &[Vec<(f64, f64)>] {
+        self.scenarios.get_or_init(|| {
+            let mut rng = match self.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            let steps = self.factors.iter().map(|(_, process)| process.get_number_of_steps()).max().unwrap_or(0);
+            (0..self.num_scenarios)
+                .map(|_| {
+                    let mut shocks = vec![Vec::with_capacity(steps); self.factors.len()];
+                    for _ in 0..steps {
+                        let independent: Vec<f64> = self.factors.iter().map(|_| rng.sample(StandardNormal)).collect();
+                        for (i, factor_shocks) in shocks.iter_mut().enumerate() {
+                            factor_shocks.push(match &self.cholesky {
+                                Some(lower) => lower[i].iter().zip(&independent).map(|(l, z)| l * z).sum(),
+                                None => independent[i],
+                            });
+                        }
+                    }
+                    self.factors
+                        .iter()
+                        .zip(&shocks)
+                        .map(|((_, process), factor_shocks)| {
+                            let path = process.generate_path(&factor_shocks[..process.get_number_of_steps()]);
+                            (path[0], path[path.len() - 1])
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+    }
+
+    /// Portfolio P&L per simulated scenario.
+    pub fn portfolio_pnl(&self, positions: &HashMap<String, f64>) -> Result<Vec<f64>, VarError> {
+        if let Some(lower) = self.cholesky.as_ref().filter(|lower| lower.len() != self.factors.len()) {
+            return Err(VarError::InvalidData(format!(
+                "correlation matrix covers {} factors, the engine has {}",
+                lower.len(),
+                self.factors.len()
+            )));
+        }
+        let mut held = Vec::with_capacity(positions.len());
+        for (id, size) in positions {
+            let (factor, revaluation) = self.positions.get(id).ok_or_else(|| VarError::UnknownPosition(id.clone()))?;
+            held.push((*size, *factor, revaluation));
+        }
+        Ok(self
+            .scenarios()
+            .iter()
+            .map(|moves| {
+                held.iter()
+                    .map(|(size, factor, revaluation)| {
+                        let (start, end) = moves[*factor];
+                        size * revaluation(start, end)
+                    })
+                    .sum()
+            })
+            .collect())
+    }
+}
+
+/// Lower Cholesky factor of a positive semi-definite matrix; columns with a zero pivot stay zero.
+fn cholesky(values: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = values.len();
+    let mut lower = vec![vec![0.0; n]; n];
+    for j in 0..n {
+        let pivot = values[j][j] - (0..j).map(|k| lower[j][k] * lower[j][k]).sum::<f64>();
+        if pivot <= 1e-12 {
+            continue;
+        }
+        lower[j][j] = pivot.sqrt();
+        for i in j + 1..n {
+            lower[i][j] = (values[i][j] - (0..j).map(|k| lower[i][k] * lower[j][k]).sum::<f64>()) / lower[j][j];
+        }
+    }
+    lower
+}
+
+impl VarEngine for MonteCarloVar {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError> {
+        if positions.is_empty() {
+            return Ok(RiskMeasures::default());
+        }
+        Ok(tail_measures(&self.portfolio_pnl(positions)?, self.confidence))
+    }
+}
+
+impl fmt::Debug for MonteCarloVar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MonteCarloVar")
+            .field("confidence", &self.confidence)
+            .field("num_scenarios", &self.num_scenarios)
+            .field("factors", &self.factors.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .field("correlated", &self.cholesky.is_some())
+            .field("seed", &self.seed)
+            .field("positions", &self.positions.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template_method_pattern::{GeometricBrownianMotion, Vasicek};
+
+    #[test]
+    fn test_monte_carlo_var_matches_analytic_gbm() {
+        let one_day = 1.0 / 252.0;
+        let engine = MonteCarloVar::new(0.99, 20_000)
+            .unwrap()
+            .with_seed(7)
+            .with_factor("SPX", Box::new(GeometricBrownianMotion {
+                initial_value: 100.0,
+                risk_free_rate: 0.0,
+                volatility: 0.2,
+                time_steps: 1,
+                maturity: one_day,
+            }))
+            .with_factor("ShortRate", Box::new(Vasicek {
+                initial_value: 0.05,
+                risk_free_rate: 0.05,
+                mean_reversion: 0.0,
+                volatility: 0.01,
+                time_steps: 1,
+                maturity: one_day,
+            }))
+            .with_position("Stock", "SPX", linear(1.0))
+            .unwrap()
+            .with_position("Bond", "ShortRate", dv01(50.0))
+            .unwrap();
+
+        let stock_only = HashMap::from([("Stock".to_string(), 100.0)]);
+        let measures = engine.measure(&stock_only).unwrap();
+        // Analytic one-day 99% VaR of 10,000 notional at 20% vol is roughly 2.326 * 0.2 / sqrt(252) * 10,000.
+        let analytic = 2.326 * 0.2 / 252f64.sqrt() * 10_000.0;
+        assert!((measures.var - analytic).abs() / analytic < 0.1, "VaR {} vs {}", measures.var, analytic);
+        assert!(measures.expected_shortfall.unwrap() > measures.var);
+        // Cached scenarios: the same book gives the same figure.
+        assert_eq!(engine.measure(&stock_only).unwrap(), measures);
+
+        let both = HashMap::from([("Stock".to_string(), 100.0), ("Bond".to_string(), 1.0)]);
+        assert!(engine.measure(&both).unwrap().var > measures.var * 0.9);
+        assert!(matches!(
+            engine.measure(&HashMap::from([("Swap".to_string(), 1.0)])),
+            Err(VarError::UnknownPosition(_))
+        ));
+    }
+
+    #[test]
+    fn test_correlated_factors_diversify() {
+        let one_day = 1.0 / 252.0;
+        let index = |initial_value| GeometricBrownianMotion {
+            initial_value,
+            risk_free_rate: 0.0,
+            volatility: 0.2,
+            time_steps: 1,
+            maturity: one_day,
+        };
+        let engine = |correlation: f64| {
+            MonteCarloVar::new(0.99, 5_000)
+                .unwrap()
+                .with_seed(11)
+                .with_factor("SPX", Box::new(index(100.0)))
+                .with_factor("SX5E", Box::new(index(100.0)))
+                .with_correlation(vec![vec![1.0, correlation], vec![correlation, 1.0]])
+                .unwrap()
+                .with_position("US", "SPX", linear(1.0))
+                .unwrap()
+                .with_position("EU", "SX5E", linear(1.0))
+                .unwrap()
+        };
+        let book = HashMap::from([("US".to_string(), 100.0), ("EU".to_string(), 100.0)]);
+        let independent = engine(0.0).measure(&book).unwrap().var;
+        let comoving = engine(1.0).measure(&book).unwrap().var;
+        let offsetting = engine(-1.0).measure(&book).unwrap().var;
+        assert!(comoving > independent * 1.3, "{} vs {}", comoving, independent);
+        assert!(offsetting < independent * 0.05, "{} vs {}", offsetting, independent);
+        assert_eq!(engine(0.5).measure(&book).unwrap(), engine(0.5).measure(&book).unwrap());
+        assert!(matches!(
+            MonteCarloVar::new(0.99, 10).unwrap().with_factor("SPX", Box::new(index(100.0))).with_correlation(vec![vec![2.0]]),
+            Err(VarError::InvalidData(_))
+        ));
+
+        // A factor added after the correlation is not covered by it.
+        let extended = engine(0.5).with_factor("NKY", Box::new(index(100.0))).with_position("JP", "NKY", linear(1.0)).unwrap();
+        assert!(matches!(extended.measure(&book), Err(VarError::InvalidData(_))));
+        assert!(matches!(
+            MonteCarloVar::new(0.99, 10).unwrap().with_factor("SPX", Box::new(index(100.0))).with_correlation(vec![vec![1.0, 0.5]]),
+            Err(VarError::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,677 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::var_engine::RiskMetric;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+/// A risk alert raised by one of the `RiskState`s.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub severity: Severity,
+    pub subject: String,
+    pub message: String,
+    pub current_var: f64,
+    pub var_limit: f64,
+    pub warning_level: f64,
+    /// The metric closest to (or furthest beyond) its limit, with its value and limit.
+    pub trigger: RiskMetric,
+    pub trigger_value: f64,
+    pub trigger_limit: f64,
+    pub timestamp: SystemTime,
+}
+
+impl Alert {
+    /// One-line rendering shared by the line based backends.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "[{}] {}: {} (VaR {:.2}, warning {:.2}, limit {:.2})",
+            self.severity, self.subject, self.message, self.current_var, self.warning_level, self.var_limit
+        );
+        if self.trigger != RiskMetric::Var {
+            summary.push_str(&format!(
+                " triggered by {} {:.2} against limit {:.2}",
+                self.trigger, self.trigger_value, self.trigger_limit
+            ));
+        }
+        summary
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Io(io::Error),
+    Protocol(String),
+    Multiple(Vec<NotifyError>),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Io(err) => write!(f, "I/O error: {}", err),
+            NotifyError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            NotifyError::Multiple(errors) => {
+                write!(f, "{} notifiers failed:", errors.len())?;
+                for err in errors {
+                    write!(f, " [{}]", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+impl From<io::Error> for NotifyError {
+    fn from(err: io::Error) -> Self {
+        NotifyError::Io(err)
+    }
+}
+
+pub trait Notifier: fmt::Debug {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError>;
+}
+
+/// Prints alerts to stdout. Used when no other notifier is configured.
+#[derive(Debug, Default)]
+pub struct ConsoleNotifier;
+
+impl Notifier for ConsoleNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        println!("Sending notification: {}", alert.summary());
+        Ok(())
+    }
+}
+
+/// Sends every alert to all of the wrapped notifiers, even if some of them fail.
+#[derive(Debug, Default)]
+pub struct FanOutNotifier {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl FanOutNotifier {
+    pub fn new() -> Self {
+        FanOutNotifier { notifiers: Vec::new() }
+    }
+
+    pub fn with(mut self, notifier: Box<dyn Notifier>) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    pub fn add(&mut self, notifier: Box<dyn Notifier>) {
+        self.notifiers.push(notifier);
+    }
+}
+
+impl Notifier for FanOutNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let errors: Vec<NotifyError> = self
+            .notifiers
+            .iter()
+            .filter_map(|notifier| notifier.notify(alert).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(NotifyError::Multiple(errors))
+        }
+    }
+}
+
+/// Passes on only the alerts at or above `min_severity`, e.g. to keep paging for critical alerts.
+#[derive(Debug)]
+pub struct SeverityFilter {
+    pub min_severity: Severity,
+    notifier: Box<dyn Notifier>,
+}
+
+impl SeverityFilter {
+    pub fn new(min_severity: Severity, notifier: Box<dyn Notifier>) -> Self {
+        SeverityFilter { min_severity, notifier }
+    }
+}
+
+impl Notifier for SeverityFilter {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        if alert.severity < self.min_severity {
+            return Ok(());
+        }
+        self.notifier.notify(alert)
+    }
+}
+
+/// Minimal SMTP client (RFC 5321) speaking plain text to a relay.
+#[derive(Debug, Clone)]
+pub struct SmtpNotifier {
+    pub server: String,
+    pub helo_domain: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl SmtpNotifier {
+    pub fn new(server: &str, from: &str, to: &[&str]) -> Self {
+        SmtpNotifier {
+            server: server.to_string(),
+            helo_domain: "localhost".to_string(),
+            from: from.to_string(),
+            to: to.iter().map(|addr| addr.to_string()).collect(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    fn expect(reader: &mut impl BufRead, code: u16) -> Result<(), NotifyError> {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(NotifyError::Protocol("connection closed by SMTP server".to_string()));
+            }
+            let line = line.trim_end();
+            let received: u16 = line
+                .get(..3)
+                .and_then(|c| c.parse().ok())
+                .ok_or_else(|| NotifyError::Protocol(format!("malformed SMTP reply: {}", line)))?;
+            // "250-..." continues a multi-line reply, "250 ..." ends it.
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            return if received == code {
+                Ok(())
+            } else {
+                Err(NotifyError::Protocol(format!("expected {}, got: {}", code, line)))
+            };
+        }
+    }
+
+    fn command(
+        stream: &mut TcpStream,
+        reader: &mut impl BufRead,
+        line: &str,
+        code: u16,
+    ) -> Result<(), NotifyError> {
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        Self::expect(reader, code)
+    }
+
+    fn body(&self, alert: &Alert) -> String {
+        let mut body = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: [{}] {}\r\n\r\n",
+            single_line(&self.from),
+            self.to.iter().map(|addr| format!("<{}>", single_line(addr))).collect::<Vec<_>>().join(", "),
+            alert.severity,
+            single_line(&alert.subject)
+        );
+        for line in alert.summary().lines() {
+            // Dot-stuffing so a line starting with '.' does not end the DATA section.
+            if line.starts_with('.') {
+                body.push('.');
+            }
+            body.push_str(line);
+            body.push_str("\r\n");
+        }
+        body.push_str(".\r\n");
+        body
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let mut stream = connect(&self.server, self.timeout)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        Self::expect(&mut reader, 220)?;
+        Self::command(&mut stream, &mut reader, &format!("HELO {}", self.helo_domain), 250)?;
+        Self::command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", single_line(&self.from)), 250)?;
+        for recipient in &self.to {
+            Self::command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", single_line(recipient)), 250)?;
+        }
+        Self::command(&mut stream, &mut reader, "DATA", 354)?;
+        stream.write_all(self.body(alert).as_bytes())?;
+        Self::expect(&mut reader, 250)?;
+        Self::command(&mut stream, &mut reader, "QUIT", 221)
+    }
+}
+
+/// Posts alerts as JSON to a plain `http://` endpoint.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Self {
+        WebhookNotifier {
+            url: url.to_string(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn split_url(&self) -> Result<(&str, &str), NotifyError> {
+        let rest = self
+            .url
+            .strip_prefix("http://")
+            .ok_or_else(|| NotifyError::Protocol(format!("unsupported webhook url: {}", self.url)))?;
+        Ok(match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        })
+    }
+
+    fn payload(alert: &Alert) -> String {
+        format!(
+            "{{\"severity\":\"{}\",\"subject\":\"{}\",\"message\":\"{}\",\"current_var\":{},\"var_limit\":{},\"warning_level\":{},\"trigger\":\"{}\",\"trigger_value\":{},\"trigger_limit\":{},\"timestamp\":\"{}\"}}",
+            alert.severity,
+            json_escape(&alert.subject),
+            json_escape(&alert.message),
+            alert.current_var,
+            alert.var_limit,
+            alert.warning_level,
+            alert.trigger,
+            alert.trigger_value,
+            alert.trigger_limit,
+            format_rfc3339(alert.timestamp)
+        )
+    }
+
+    /// The address to connect to and the raw HTTP request posting `alert`.
+    pub(crate) fn request(&self, alert: &Alert) -> Result<(String, String), NotifyError> {
+        let (host, path) = self.split_url()?;
+        let authority = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+        let body = Self::payload(alert);
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            path,
+            host,
+            body.len()
+        );
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", single_line(name), single_line(value)));
+        }
+        request.push_str("\r\n");
+        request.push_str(&body);
+        Ok((authority, request))
+    }
+
+    pub(crate) fn check_status(status_line: &str) -> Result<(), NotifyError> {
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| NotifyError::Protocol(format!("malformed HTTP response: {}", status_line.trim_end())))?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(NotifyError::Protocol(format!("webhook returned HTTP {}", status)))
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let (authority, request) = self.request(alert)?;
+        let mut stream = connect(&authority, self.timeout)?;
+        stream.write_all(request.as_bytes())?;
+        let mut status_line = String::new();
+        BufReader::new(&stream).read_line(&mut status_line)?;
+        Self::check_status(&status_line)
+    }
+}
+
+/// RFC 5424 style syslog line format.
+#[derive(Debug, Clone)]
+pub struct SyslogFormat {
+    pub facility: u8,
+    pub hostname: String,
+    pub app_name: String,
+}
+
+impl SyslogFormat {
+    pub fn new(app_name: &str) -> Self {
+        SyslogFormat {
+            facility: 16, // local0
+            hostname: "-".to_string(),
+            app_name: app_name.to_string(),
+        }
+    }
+
+    pub fn format(&self, alert: &Alert) -> String {
+        let level = match alert.severity {
+            Severity::Info => 6,
+            Severity::Warning => 4,
+            Severity::Critical => 2,
+        };
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            self.facility as u32 * 8 + level,
+            format_rfc3339(alert.timestamp),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            alert.summary()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LineFormat {
+    Plain,
+    Syslog(SyslogFormat),
+}
+
+impl LineFormat {
+    pub fn format(&self, alert: &Alert) -> String {
+        match self {
+            LineFormat::Plain => format!("{} {}", format_rfc3339(alert.timestamp), alert.summary()),
+            LineFormat::Syslog(syslog) => syslog.format(alert),
+        }
+    }
+}
+
+/// Appends one line per alert to a file. The file is never truncated.
+#[derive(Debug, Clone)]
+pub struct FileNotifier {
+    pub path: PathBuf,
+    pub format: LineFormat,
+}
+
+impl FileNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileNotifier {
+            path: path.into(),
+            format: LineFormat::Plain,
+        }
+    }
+
+    pub fn with_format(mut self, format: LineFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", self.format.format(alert))?;
+        Ok(())
+    }
+}
+
+/// Sends syslog formatted datagrams to a syslog collector over UDP.
+#[derive(Debug, Clone)]
+pub struct SyslogNotifier {
+    pub collector: String,
+    pub format: SyslogFormat,
+}
+
+impl SyslogNotifier {
+    pub fn new(collector: &str, format: SyslogFormat) -> Self {
+        SyslogNotifier {
+            collector: collector.to_string(),
+            format,
+        }
+    }
+}
+
+impl Notifier for SyslogNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.send_to(self.format.format(alert).as_bytes(), self.collector.as_str())?;
+        Ok(())
+    }
+}
+
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream, NotifyError> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| NotifyError::Protocol(format!("could not resolve {}", address)))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// `value` with line breaks replaced by spaces, so it cannot end a header or protocol line early.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats a `SystemTime` as an RFC 3339 UTC timestamp with millisecond precision.
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (hour, minute, second) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn alert() -> Alert {
+        Alert {
+            severity: Severity::Critical,
+            subject: "Limit Breach".to_string(),
+            message: "New trades are blocked.".to_string(),
+            current_var: 105.0,
+            var_limit: 100.0,
+            warning_level: 80.0,
+            trigger: RiskMetric::Var,
+            trigger_value: 105.0,
+            trigger_limit: 100.0,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        }
+    }
+
+    /// Accepts a single SMTP session and returns the commands and DATA it received.
+    fn mock_smtp_server() -> (String, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = Vec::new();
+            stream.write_all(b"220 mock ESMTP\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                received.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else if line.starts_with("HELO") {
+                    b"250-mock greets you\r\n250 OK\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+            tx.send(received).unwrap();
+        });
+        (address, rx)
+    }
+
+    #[test]
+    fn test_smtp_notifier_against_mock_server() {
+        let (address, received) = mock_smtp_server();
+        let notifier = SmtpNotifier::new(&address, "risk@example.com", &["desk@example.com", "cro@example.com"]);
+        notifier.notify(&alert()).unwrap();
+
+        let session = received.recv().unwrap();
+        assert_eq!(session[0], "HELO localhost");
+        assert_eq!(session[1], "MAIL FROM:<risk@example.com>");
+        assert_eq!(session[2], "RCPT TO:<desk@example.com>");
+        assert_eq!(session[3], "RCPT TO:<cro@example.com>");
+        assert_eq!(session[4], "DATA");
+        assert!(session.contains(&"Subject: [CRITICAL] Limit Breach".to_string()));
+        assert_eq!(session.last().unwrap(), "QUIT");
+
+        // Line breaks in the subject cannot add headers or end the header block.
+        let injected = Alert { subject: "Limit Breach\r\nBcc: <attacker@example.com>\r\n\r\nfake".to_string(), ..alert() };
+        let body = notifier.body(&injected);
+        let headers: Vec<&str> = body.split("\r\n\r\n").next().unwrap().split("\r\n").collect();
+        assert_eq!(headers.len(), 3);
+        assert!(headers[2].starts_with("Subject: [CRITICAL] Limit Breach  Bcc: "));
+    }
+
+    /// Accepts a single HTTP request, answers it with `status_line` and returns the request.
+    fn mock_http_server(status_line: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            io::Read::read_exact(&mut reader, &mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            stream.write_all(format!("{}\r\nContent-Length: 0\r\n\r\n", status_line).as_bytes()).unwrap();
+            tx.send(request).unwrap();
+        });
+        (address, rx)
+    }
+
+    #[test]
+    fn test_webhook_and_syslog_notifiers_against_loopback() {
+        let (address, received) = mock_http_server("HTTP/1.1 204 No Content");
+        let notifier = WebhookNotifier::new(&format!("http://{}/hooks/risk", address)).with_header("X-Token", "abc");
+        notifier.notify(&alert()).unwrap();
+        let request = received.recv().unwrap();
+        assert!(request.starts_with(&format!("POST /hooks/risk HTTP/1.1\r\nHost: {}\r\n", address)));
+        assert!(request.contains("\r\nX-Token: abc\r\n"));
+        assert!(request.ends_with(
+            "\r\n\r\n{\"severity\":\"CRITICAL\",\"subject\":\"Limit Breach\",\"message\":\"New trades are blocked.\",\"current_var\":105,\"var_limit\":100,\"warning_level\":80,\"trigger\":\"VaR\",\"trigger_value\":105,\"trigger_limit\":100,\"timestamp\":\"2023-11-14T22:13:20.000Z\"}"
+        ));
+
+        let (address, received) = mock_http_server("HTTP/1.1 503 Service Unavailable");
+        let err = WebhookNotifier::new(&format!("http://{}", address)).notify(&alert()).unwrap_err();
+        assert!(matches!(err, NotifyError::Protocol(ref reason) if reason == "webhook returned HTTP 503"));
+        assert!(received.recv().unwrap().starts_with("POST / HTTP/1.1\r\n"));
+
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let syslog = SyslogNotifier::new(&collector.local_addr().unwrap().to_string(), SyslogFormat::new("risk"));
+        syslog.notify(&alert()).unwrap();
+        let mut datagram = [0; 1024];
+        let (len, _) = collector.recv_from(&mut datagram).unwrap();
+        let datagram = std::str::from_utf8(&datagram[..len]).unwrap();
+        assert_eq!(
+            datagram,
+            format!("<130>1 2023-11-14T22:13:20.000Z - risk {} - - {}", std::process::id(), alert().summary())
+        );
+    }
+
+    #[test]
+    fn test_fan_out_reports_failures_but_notifies_everyone() {
+        let path = std::env::temp_dir().join(format!("risk_alerts_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fan_out = FanOutNotifier::new()
+            .with(Box::new(WebhookNotifier::new("https://unsupported.example.com/hook")))
+            .with(Box::new(FileNotifier::new(&path).with_format(LineFormat::Syslog(SyslogFormat::new("risk")))));
+
+        let err = fan_out.notify(&alert()).unwrap_err();
+        assert!(matches!(err, NotifyError::Multiple(ref errors) if errors.len() == 1));
+        fan_out.notify(&alert()).unwrap_err();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("<130>1 2023-11-14T22:13:20.000Z - risk "));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::StateKind;
+
+/// Damping applied by `RiskManager` so a VaR hovering around a threshold does not flap between states.
+///
+/// Escalations always happen immediately; only moves towards a less severe state are
+/// subject to the hysteresis bands and minimum dwell times. `escalate_after` additionally
+/// forces Warning and LimitBreach one step up once they have lasted long enough.
+#[derive(Debug, Clone, Default)]
+pub struct TransitionPolicy {
+    /// Warning is only left for Normal once VaR drops below `warning_level - warning_band`.
+    pub warning_band: f64,
+    /// LimitBreach is only left once VaR drops below `var_limit - limit_band`.
+    pub limit_band: f64,
+    pub min_dwell: HashMap<StateKind, Duration>,
+    pub escalate_after: HashMap<StateKind, Duration>,
+}
+
+impl TransitionPolicy {
+    pub fn new() -> Self {
+        TransitionPolicy::default()
+    }
+
+    /// Checked against the limits by `RiskManager::with_policy`.
+    pub fn with_hysteresis(mut self, warning_band: f64, limit_band: f64) -> Self {
+        self.warning_band = warning_band;
+        self.limit_band = limit_band;
+        self
+    }
+
+    pub fn with_min_dwell(mut self, state: StateKind, dwell: Duration) -> Self {
+        self.min_dwell.insert(state, dwell);
+        self
+    }
+
+    pub fn with_escalation(mut self, state: StateKind, after: Duration) -> Self {
+        self.escalate_after.insert(state, after);
+        self
+    }
+
+    pub fn escalate_after(&self, state: StateKind) -> Option<Duration> {
+        self.escalate_after.get(&state).copied()
+    }
+
+    pub fn min_dwell(&self, state: StateKind) -> Duration {
+        self.min_dwell.get(&state).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::config::RiskLimits;
+use super::journal::{crc32, json_number, nanos_since_epoch, read_json_line, scan_records, Json, JournalError, Scan};
+use super::notifier::json_escape;
+use super::snapshot::RiskSnapshot;
+use super::var_engine::RiskMeasures;
+
+/// An input that changed the book or the state machine. The book is only ever changed by
+/// applying these, so replaying them reproduces it exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookEvent {
+    PositionSet { position_id: String, size: f64 },
+    PositionRemoved { position_id: String },
+    /// Fills the engines reported together, applied before VaR is recomputed.
+    Filled { fills: Vec<(String, f64)> },
+    /// A `tick` that moved the state machine, e.g. a time based escalation.
+    Tick,
+    ShutdownReset { operator_id: String, reason: String },
+    LimitsChanged { limits: RiskLimits },
+    /// The manager resumed from a snapshot, or the log was attached to a manager that already held a book.
+    Restored { snapshot: RiskSnapshot },
+    /// `revalue` recomputed the risk of an unchanged book, e.g. after market data moved.
+    Revalued { measures: RiskMeasures },
+    /// The engine watchdog found the engines lost, or back.
+    EngineAvailability { alive: bool },
+}
+
+impl BookEvent {
+    /// Applies the event to the book. Returns false if it left the book unchanged.
+    pub fn apply(&self, positions: &mut HashMap<String, f64>) -> bool {
+        match self {
+            BookEvent::PositionSet { position_id, size } => {
+                positions.insert(position_id.clone(), *size);
+                true
+            }
+            BookEvent::PositionRemoved { position_id } => positions.remove(position_id).is_some(),
+            BookEvent::Filled { fills } => {
+                for (position_id, quantity) in fills {
+                    let size = positions.entry(position_id.clone()).or_insert(0.0);
+                    *size += quantity;
+                    if size.abs() < 1e-9 {
+                        positions.remove(position_id);
+                    }
+                }
+                !fills.is_empty()
+            }
+            BookEvent::Restored { snapshot } => {
+                *positions = snapshot.positions.iter().map(|(id, size)| (id.clone(), *size)).collect();
+                true
+            }
+            BookEvent::Tick
+            | BookEvent::ShutdownReset { .. }
+            | BookEvent::LimitsChanged { .. }
+            | BookEvent::Revalued { .. }
+            | BookEvent::EngineAvailability { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    pub at: SystemTime,
+    pub event: BookEvent,
+}
+
+/// Where `RiskManager` records its `BookEvent`s.
+pub trait EventLog: fmt::Debug {
+    fn record(&mut self, event: &RecordedEvent);
+}
+
+/// Keeps the events in memory. Clones share the same log.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEventLog {
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+impl MemoryEventLog {
+    pub fn new() -> Self {
+        MemoryEventLog::default()
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventLog for MemoryEventLog {
+    fn record(&mut self, event: &RecordedEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Appends the events to a file, one checksummed JSON object per line, as the journal does.
+#[derive(Debug)]
+pub struct FileEventLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileEventLog {
+    /// Opens `path` for appending. A torn last line is cut off first, as `JournalWriter::open` does.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let scan = scan_log(&bytes)?;
+        if let Some((record, reason)) = scan.torn {
+            file.set_len(scan.valid_len as u64)?;
+            eprintln!(
+                "Truncated torn book event {} ({} bytes) in {}: {}",
+                record,
+                bytes.len() - scan.valid_len,
+                path.display(),
+                reason
+            );
+        }
+        Ok(FileEventLog { path, file })
+    }
+
+    /// Reads back a log written by `FileEventLog`, verifying every checksum.
+    ///
+    /// A torn last line, left by a crash during an append, is skipped; corruption anywhere
+    /// before it is an error.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<RecordedEvent>, JournalError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Ok(scan_log(&bytes)?.entries)
+    }
+}
+
+impl EventLog for FileEventLog {
+    fn record(&mut self, event: &RecordedEvent) {
+        let written = self.file.write_all(encode(event).as_bytes()).and_then(|_| self.file.flush());
+        if let Err(err) = written {
+            eprintln!("Failed to record book event to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+fn scan_log(bytes: &[u8]) -> Result<Scan<RecordedEvent>, JournalError> {
+    scan_records(bytes, |pos| read_json_line(bytes, pos, decode))
+}
+
+fn encode(event: &RecordedEvent) -> String {
+    let fields = match &event.event {
+        BookEvent::PositionSet { position_id, size } => format!(
+            "\"type\":\"PositionSet\",\"position_id\":\"{}\",\"size\":{}",
+            json_escape(position_id),
+            json_number(*size)
+        ),
+        BookEvent::PositionRemoved { position_id } => {
+            format!("\"type\":\"PositionRemoved\",\"position_id\":\"{}\"", json_escape(position_id))
+        }
+        BookEvent::Filled { fills } => {
+            let fills: Vec<String> = fills
+                .iter()
+                .enumerate()
+                .map(|(idx, (position_id, quantity))| {
+                    format!("\"{}\":{{\"{}\":{}}}", idx, json_escape(position_id), json_number(*quantity))
+                })
+                .collect();
+            format!("\"type\":\"Filled\",\"fills\":{{{}}}", fills.join(","))
+        }
+        BookEvent::Tick => "\"type\":\"Tick\"".to_string(),
+        BookEvent::ShutdownReset { operator_id, reason } => format!(
+            "\"type\":\"ShutdownReset\",\"operator_id\":\"{}\",\"reason\":\"{}\"",
+            json_escape(operator_id),
+            json_escape(reason)
+        ),
+        BookEvent::LimitsChanged { limits } => format!(
+            "\"type\":\"LimitsChanged\",\"var_limit\":{},\"warning_level\":{},\"shutdown_multiplier\":{},\"warning_band\":{},\"limit_band\":{}",
+            json_number(limits.var_limit),
+            json_number(limits.warning_level),
+            json_number(limits.shutdown_multiplier),
+            json_number(limits.warning_band),
+            json_number(limits.limit_band)
+        ),
+        BookEvent::Restored { snapshot } => {
+            format!("\"type\":\"Restored\",\"snapshot\":\"{}\"", json_escape(snapshot.encode().trim_end()))
+        }
+        BookEvent::Revalued { measures } => format!(
+            "\"type\":\"Revalued\",\"var\":{},\"expected_shortfall\":{}",
+            json_number(measures.var),
+            measures.expected_shortfall.map_or("null".to_string(), json_number)
+        ),
+        BookEvent::EngineAvailability { alive } => format!("\"type\":\"EngineAvailability\",\"alive\":{}", *alive as u8),
+    };
+    let body = format!("{{\"at_ns\":{},{}}}", nanos_since_epoch(event.at), fields);
+    format!("{{\"crc32\":\"{:08x}\",\"event\":{}}}\n", crc32(body.as_bytes()), body)
+}
+
+fn decode(line: &str) -> Result<RecordedEvent, String> {
+    let line = line.trim_end();
+    let prefix = "{\"crc32\":\"";
+    let checksum = line.strip_prefix(prefix).and_then(|rest| rest.get(..8)).ok_or("missing checksum")?;
+    let expected = u32::from_str_radix(checksum, 16).map_err(|err| err.to_string())?;
+    let body = line[prefix.len() + 8..]
+        .strip_prefix("\",\"event\":")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or("malformed event")?;
+    if crc32(body.as_bytes()) != expected {
+        return Err("checksum mismatch".to_string());
+    }
+    let object = match Json::parse(body)? {
+        Json::Object(object) => object,
+        _ => return Err("event is not an object".to_string()),
+    };
+    let text = |key: &str| match object.get(key) {
+        Some(Json::Text(text)) => Ok(text.clone()),
+        _ => Err(format!("missing {}", key)),
+    };
+    let number = |value: Option<&Json>| match value {
+        Some(Json::Number(number)) => Ok(*number),
+        Some(Json::Integer(integer)) => Ok(*integer as f64),
+        _ => Err("missing number".to_string()),
+    };
+    let at = match object.get("at_ns") {
+        Some(Json::Integer(nanos)) => UNIX_EPOCH + Duration::from_nanos(*nanos),
+        _ => return Err("missing at_ns".to_string()),
+    };
+    let event = match text("type")?.as_str() {
+        "PositionSet" => BookEvent::PositionSet { position_id: text("position_id")?, size: number(object.get("size"))? },
+        "PositionRemoved" => BookEvent::PositionRemoved { position_id: text("position_id")? },
+        "Filled" => {
+            let fills = match object.get("fills") {
+                Some(Json::Object(fills)) => fills,
+                _ => return Err("missing fills".to_string()),
+            };
+            // Keys are the fill's position in the batch; BTreeMap would order "10" before "2".
+            let mut ordered = Vec::with_capacity(fills.len());
+            for (idx, fill) in fills {
+                let idx: usize = idx.parse().map_err(|_| format!("bad fill index {}", idx))?;
+                match fill {
+                    Json::Object(fill) if fill.len() == 1 => {
+                        let (position_id, quantity) = fill.iter().next().unwrap();
+                        ordered.push((idx, (position_id.clone(), number(Some(quantity))?)));
+                    }
+                    _ => return Err("malformed fill".to_string()),
+                }
+            }
+            ordered.sort_by_key(|(idx, _)| *idx);
+            BookEvent::Filled { fills: ordered.into_iter().map(|(_, fill)| fill).collect() }
+        }
+        "Tick" => BookEvent::Tick,
+        "ShutdownReset" => BookEvent::ShutdownReset { operator_id: text("operator_id")?, reason: text("reason")? },
+        "LimitsChanged" => BookEvent::LimitsChanged {
+            limits: RiskLimits {
+                var_limit: number(object.get("var_limit"))?,
+                warning_level: number(object.get("warning_level"))?,
+                shutdown_multiplier: number(object.get("shutdown_multiplier"))?,
+                warning_band: number(object.get("warning_band"))?,
+                limit_band: number(object.get("limit_band"))?,
+            },
+        },
+        "Restored" => BookEvent::Restored {
+            snapshot: RiskSnapshot::decode(&text("snapshot")?).map_err(|err| err.to_string())?,
+        },
+        "Revalued" => BookEvent::Revalued {
+            measures: RiskMeasures {
+                var: number(object.get("var"))?,
+                expected_shortfall: match object.get("expected_shortfall") {
+                    Some(Json::Null) => None,
+                    value => Some(number(value)?),
+                },
+            },
+        },
+        "EngineAvailability" => BookEvent::EngineAvailability {
+            alive: match object.get("alive") {
+                Some(Json::Integer(alive)) => *alive != 0,
+                _ => return Err("missing alive".to_string()),
+            },
+        },
+        other => return Err(format!("unknown event type {}", other)),
+    };
+    Ok(RecordedEvent { at, event })
+}
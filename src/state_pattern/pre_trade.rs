@@ -0,0 +1,24 @@
+use super::StateKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderVerdict {
+    Approve,
+    ApproveWithWarning,
+    Reject,
+}
+
+/// Outcome of `RiskManager::check_order`: what the book would look like if the order were filled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderCheck {
+    pub verdict: OrderVerdict,
+    pub projected_var: f64,
+    pub projected_es: Option<f64>,
+    pub projected_state: StateKind,
+    pub reason: String,
+}
+
+impl OrderCheck {
+    pub fn is_approved(&self) -> bool {
+        self.verdict != OrderVerdict::Reject
+    }
+}
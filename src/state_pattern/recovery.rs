@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::var_engine::RiskMetric;
+use super::StateKind;
+
+/// An operator's request to leave `ShutdownState`.
+#[derive(Debug, Clone)]
+pub struct ResetRequest {
+    pub operator_id: String,
+    pub credential: String,
+    pub reason: String,
+}
+
+impl ResetRequest {
+    pub fn new(operator_id: &str, credential: &str, reason: &str) -> Self {
+        ResetRequest {
+            operator_id: operator_id.to_string(),
+            credential: credential.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResetError {
+    /// The state machine has no `OperatorReset` transition out of this state.
+    NoResetTransition(StateKind),
+    Unauthorized(String),
+    MissingReason,
+    AboveLimit { metric: RiskMetric, value: f64, limit: f64 },
+}
+
+impl fmt::Display for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResetError::NoResetTransition(state) => write!(f, "no operator reset is declared from {}", state),
+            ResetError::Unauthorized(operator) => write!(f, "operator '{}' is not authorised to reset", operator),
+            ResetError::MissingReason => write!(f, "a reason is required to reset"),
+            ResetError::AboveLimit { metric, value, limit } => {
+                write!(f, "{} {:.2} must be below the limit {:.2} to reset", metric, value, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResetError {}
+
+pub trait OperatorAuthenticator: fmt::Debug {
+    fn authenticate(&self, operator_id: &str, credential: &str) -> bool;
+}
+
+/// Authenticates operators against a fixed table of credentials. Empty by default, which denies everyone.
+#[derive(Debug, Clone, Default)]
+pub struct StaticAuthenticator {
+    credentials: HashMap<String, String>,
+}
+
+impl StaticAuthenticator {
+    pub fn new() -> Self {
+        StaticAuthenticator::default()
+    }
+
+    pub fn with_operator(mut self, operator_id: &str, credential: &str) -> Self {
+        self.credentials.insert(operator_id.to_string(), credential.to_string());
+        self
+    }
+}
+
+impl OperatorAuthenticator for StaticAuthenticator {
+    fn authenticate(&self, operator_id: &str, credential: &str) -> bool {
+        self.credentials
+            .get(operator_id)
+            .is_some_and(|expected| !expected.is_empty() && expected == credential)
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::mpsc::{self, Sender};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::events::{EventRecorder, RiskEvent, RiskEventKind};
+use super::position_book::{BookEvent, RecordedEvent};
+use super::var_engine::RiskMetric;
+use super::{CommandEnvelope, MockClock, RiskManager, StateKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub at: SystemTime,
+    pub from: StateKind,
+    pub to: StateKind,
+    pub trigger: RiskMetric,
+    /// Index of the rule taken in `StateMachine::transitions`.
+    pub rule: Option<usize>,
+}
+
+/// The state transitions among published risk events, e.g. those an `EventRecorder` collected in production.
+pub fn transitions(events: &[RiskEvent]) -> Vec<Transition> {
+    events
+        .iter()
+        .filter_map(|event| match event.kind {
+            RiskEventKind::StateEntered { from, to, trigger, rule, .. } => Some(Transition { at: event.timestamp, from, to, trigger, rule }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct ReplayOutcome {
+    pub manager: RiskManager,
+    pub transitions: Vec<Transition>,
+    /// Every command the replayed manager sent, heartbeats included.
+    pub commands: Vec<CommandEnvelope>,
+}
+
+/// Rebuilds a `RiskManager` from the events its `EventLog` recorded.
+///
+/// `build` must configure the manager as in production (limits, policy, VaR engine) and use
+/// the clock it is given, which replay moves to each event's time before applying it. Operator
+/// resets are replayed without re-authenticating. Revaluations use the figures production logged,
+/// and engine liveness follows the watchdog's logged findings rather than the replayed engines;
+/// later position changes are measured with the VaR engine `build` configures.
+pub fn replay<F>(events: &[RecordedEvent], build: F) -> ReplayOutcome
+where
+    F: FnOnce(Sender<CommandEnvelope>, MockClock) -> RiskManager,
+{
+    let clock = MockClock::new(events.first().map_or(UNIX_EPOCH, |event| event.at));
+    let (sender, commands) = mpsc::channel();
+    let mut manager = build(sender, clock.clone());
+    let recorder = EventRecorder::new();
+    let subscription = manager.subscribe(Box::new(recorder.clone()));
+    manager.replayed_liveness = Some(true);
+
+    for recorded in events {
+        clock.set(recorded.at);
+        match &recorded.event {
+            BookEvent::PositionSet { position_id, size } => manager.add_position(position_id, *size),
+            BookEvent::PositionRemoved { position_id } => manager.remove_position(position_id),
+            BookEvent::Filled { fills } => manager.apply_fills(fills.clone()),
+            BookEvent::Tick => manager.tick(),
+            BookEvent::ShutdownReset { operator_id, reason } => manager.apply_reset(operator_id, reason),
+            BookEvent::LimitsChanged { limits } => manager.apply_limits(*limits),
+            // Production only records restores that passed validation.
+            BookEvent::Restored { snapshot } => {
+                if let Err(err) = manager.apply_restore(snapshot) {
+                    eprintln!("Skipping recorded restore: {}", err);
+                }
+            }
+            BookEvent::Revalued { measures } => manager.apply_revaluation(*measures),
+            BookEvent::EngineAvailability { alive } => {
+                manager.replayed_liveness = Some(*alive);
+                manager.watch_engine();
+            }
+        }
+    }
+
+    manager.unsubscribe(subscription);
+    manager.replayed_liveness = None;
+    ReplayOutcome { manager, transitions: transitions(&recorder.events()), commands: commands.try_iter().collect() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_pattern::policy::TransitionPolicy;
+    use crate::state_pattern::position_book::{FileEventLog, MemoryEventLog};
+    use crate::state_pattern::recovery::{ResetRequest, StaticAuthenticator};
+    use crate::state_pattern::var_engine::{CovarianceMatrix, ParametricVar};
+    use crate::state_pattern::EngineReport;
+    use std::time::Duration;
+
+    fn configured(sender: Sender<CommandEnvelope>, clock: MockClock) -> RiskManager {
+        let policy = TransitionPolicy::new()
+            .with_min_dwell(StateKind::Warning, Duration::from_secs(30))
+            .with_escalation(StateKind::Warning, Duration::from_secs(60));
+        RiskManager::new(100.0, 80.0, sender)
+            .with_policy(policy)
+            .unwrap()
+            .with_clock(Box::new(clock))
+            .with_authenticator(Box::new(StaticAuthenticator::new().with_operator("ops-1", "s3cret")))
+    }
+
+    #[test]
+    fn test_replay_reproduces_transitions() {
+        let path = std::env::temp_dir().join(format!("risk_book_events_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let advance = |seconds: u64| clock.advance(Duration::from_secs(seconds));
+        let (sender, _receiver) = mpsc::channel();
+        let (reports, report_receiver) = mpsc::channel();
+        let recorder = EventRecorder::new();
+        let mut production = configured(sender, clock.clone())
+            .with_engine_reports(report_receiver, Duration::from_secs(3600))
+            .with_event_log(Box::new(FileEventLog::open(&path).unwrap()));
+        production.subscribe(Box::new(recorder.clone()));
+
+        production.add_position("Position1", 50.0);
+        advance(5);
+        production.add_position("Position2", 35.0);
+        advance(10);
+        production.remove_position("Position2"); // held in Warning by the dwell time
+        advance(25);
+        production.tick();
+        advance(10);
+        production.add_position("Position2", 35.0);
+        advance(61);
+        production.tick(); // Warning escalates
+        production.add_position("Position3", 40.0);
+        production.remove_position("Position3");
+        reports.send(EngineReport::Fill { position_id: "Position1".to_string(), quantity: -40.0 }).unwrap();
+        advance(1);
+        production.tick();
+        production.reset_shutdown(&ResetRequest::new("ops-1", "s3cret", "Positions cut by the desk")).unwrap();
+        let expected = transitions(&recorder.events());
+        assert_eq!(
+            expected.iter().map(|transition| transition.to).collect::<Vec<_>>(),
+            [StateKind::Warning, StateKind::Normal, StateKind::Warning, StateKind::LimitBreach, StateKind::Shutdown, StateKind::Recovery]
+        );
+
+        let events = FileEventLog::read(&path).unwrap();
+        // A crash during an append leaves a torn last line: reading skips it and reopening cuts it off.
+        let intact = std::fs::metadata(&path).unwrap().len();
+        let mut torn = std::fs::read(&path).unwrap();
+        torn.extend_from_slice(b"{\"crc32\":\"0a1b");
+        std::fs::write(&path, &torn).unwrap();
+        assert_eq!(FileEventLog::read(&path).unwrap(), events);
+        drop(FileEventLog::open(&path).unwrap());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact);
+        std::fs::remove_file(&path).unwrap();
+        let memory = MemoryEventLog::new();
+        let outcome = replay(&events, |sender, clock| configured(sender, clock).with_event_log(Box::new(memory.clone())));
+        assert_eq!(outcome.transitions, expected);
+        assert_eq!(outcome.manager.positions, production.positions);
+        assert_eq!(memory.events(), events);
+    }
+
+    #[test]
+    fn test_replay_covers_restores_revaluations_and_the_watchdog() {
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let covariance = |volatility: f64| CovarianceMatrix::new(&["spx"], vec![vec![volatility * volatility]]).unwrap();
+        let var_engine = || ParametricVar::new(0.99, covariance(1.0)).unwrap().with_exposures("Stock", &[("spx", 1.0)]);
+        let manager = |sender: Sender<CommandEnvelope>, clock: MockClock| {
+            RiskManager::new(100.0, 80.0, sender)
+                .with_clock(Box::new(clock))
+                .with_var_engine(Box::new(var_engine()))
+                .with_watchdog(Duration::from_secs(1), Duration::from_secs(5))
+        };
+        let mut previous_run = manager(mpsc::channel().0, clock.clone());
+        previous_run.add_position("Stock", 30.0);
+        let snapshot = previous_run.snapshot();
+
+        let production_engine = var_engine();
+        let market = production_engine.covariance_handle();
+        let (reports, report_receiver) = mpsc::channel();
+        let (sender, _commands) = mpsc::channel();
+        let log = MemoryEventLog::new();
+        let recorder = EventRecorder::new();
+        // The log starts after the restore, from the restored book.
+        let mut production = manager(sender, clock.clone())
+            .with_var_engine(Box::new(production_engine))
+            .with_engine_reports(report_receiver, Duration::from_secs(3600))
+            .restore(&snapshot)
+            .unwrap()
+            .with_event_log(Box::new(log.clone()));
+        production.subscribe(Box::new(recorder.clone()));
+
+        market.update(covariance(1.2));
+        production.revalue();
+        clock.advance(Duration::from_secs(6));
+        production.tick(); // the engine has gone quiet
+        reports.send(EngineReport::Heartbeat).unwrap();
+        clock.advance(Duration::from_secs(1));
+        production.tick();
+        market.update(covariance(1.6));
+        production.revalue();
+        let expected = transitions(&recorder.events());
+        assert_eq!(
+            expected.iter().map(|transition| transition.to).collect::<Vec<_>>(),
+            [StateKind::Warning, StateKind::EngineUnavailable, StateKind::Warning, StateKind::LimitBreach]
+        );
+
+        let events = log.events();
+        assert!(matches!(events[0].event, BookEvent::Restored { .. }));
+        let replayed = MemoryEventLog::new();
+        let outcome = replay(&events, |sender, clock| manager(sender, clock).with_event_log(Box::new(replayed.clone())));
+        assert_eq!(outcome.transitions, expected);
+        assert_eq!(outcome.manager.positions, production.positions);
+        assert_eq!(outcome.manager.risk_measures, production.risk_measures);
+        assert_eq!(replayed.events(), events);
+    }
+}
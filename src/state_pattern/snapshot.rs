@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::config::RiskLimits;
+use super::journal::{crc32, json_number, nanos_since_epoch, Json};
+use super::var_engine::MetricLimits;
+use super::{StateKind, ThrottleLimits};
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Corrupt(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot I/O error: {}", err),
+            SnapshotError::Corrupt(reason) => write!(f, "snapshot is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Everything `RiskManager::restore` needs to carry on where a previous process stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskSnapshot {
+    pub taken_at: SystemTime,
+    pub state: StateKind,
+    pub state_entered_at: SystemTime,
+    /// Set when LimitBreach was reached by a Warning timing out.
+    pub escalated: bool,
+    /// The state EngineUnavailable was entered from.
+    pub resume: Option<StateKind>,
+    pub var_limit: f64,
+    pub warning_level: f64,
+    pub shutdown_multiplier: f64,
+    /// Hysteresis bands of the transition policy.
+    pub warning_band: f64,
+    pub limit_band: f64,
+    pub es_limits: Option<MetricLimits>,
+    pub throttle: ThrottleLimits,
+    pub positions: BTreeMap<String, f64>,
+    /// Sequence number the next engine command will carry.
+    pub next_sequence: u64,
+}
+
+impl RiskSnapshot {
+    /// Writes the snapshot next to `path` and renames it into place, so a crash mid-write
+    /// leaves the previous snapshot intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(self.encode().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn limits(&self) -> RiskLimits {
+        RiskLimits {
+            var_limit: self.var_limit,
+            warning_level: self.warning_level,
+            shutdown_multiplier: self.shutdown_multiplier,
+            warning_band: self.warning_band,
+            limit_band: self.limit_band,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::decode(&fs::read_to_string(path)?)
+    }
+
+    /// `{"crc32":"…","snapshot":{…}}`, the checksum covering the snapshot text.
+    pub fn encode(&self) -> String {
+        let optional = |value: Option<f64>| value.map_or("null".to_string(), json_number);
+        let positions: Vec<String> = self
+            .positions
+            .iter()
+            .map(|(id, size)| format!("\"{}\":{}", super::notifier::json_escape(id), json_number(*size)))
+            .collect();
+        let body = format!(
+            "{{\"taken_at_ns\":{},\"state\":\"{:?}\",\"state_entered_at_ns\":{},\"escalated\":{},\"resume\":{},\"var_limit\":{},\"warning_level\":{},\"shutdown_multiplier\":{},\"warning_band\":{},\"limit_band\":{},\"es_warning_level\":{},\"es_limit\":{},\"max_order_size\":{},\"max_orders_per_second\":{},\"next_sequence\":{},\"positions\":{{{}}}}}",
+            nanos_since_epoch(self.taken_at),
+            self.state,
+            nanos_since_epoch(self.state_entered_at),
+            self.escalated as u8,
+            self.resume.map_or("null".to_string(), |state| format!("\"{:?}\"", state)),
+            json_number(self.var_limit),
+            json_number(self.warning_level),
+            json_number(self.shutdown_multiplier),
+            json_number(self.warning_band),
+            json_number(self.limit_band),
+            optional(self.es_limits.map(|limits| limits.warning_level)),
+            optional(self.es_limits.map(|limits| limits.limit)),
+            json_number(self.throttle.max_order_size),
+            self.throttle.max_orders_per_second,
+            self.next_sequence,
+            positions.join(","),
+        );
+        format!("{{\"crc32\":\"{:08x}\",\"snapshot\":{}}}\n", crc32(body.as_bytes()), body)
+    }
+
+    pub fn decode(text: &str) -> Result<Self, SnapshotError> {
+        let corrupt = |reason: &str| SnapshotError::Corrupt(reason.to_string());
+        let text = text.trim_end();
+        let prefix = "{\"crc32\":\"";
+        let checksum = text.strip_prefix(prefix).and_then(|rest| rest.get(..8)).ok_or_else(|| corrupt("missing checksum"))?;
+        let expected = u32::from_str_radix(checksum, 16).map_err(|_| corrupt("bad checksum"))?;
+        let body = text[prefix.len() + 8..]
+            .strip_prefix("\",\"snapshot\":")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .ok_or_else(|| corrupt("malformed snapshot"))?;
+        if crc32(body.as_bytes()) != expected {
+            return Err(corrupt("checksum mismatch"));
+        }
+
+        let object = match Json::parse(body).map_err(SnapshotError::Corrupt)? {
+            Json::Object(object) => object,
+            _ => return Err(corrupt("snapshot is not an object")),
+        };
+        let missing = |key: &str| SnapshotError::Corrupt(format!("missing {}", key));
+        let integer = |key: &str| match object.get(key) {
+            Some(Json::Integer(integer)) => Ok(*integer),
+            _ => Err(missing(key)),
+        };
+        let number = |key: &str| match object.get(key) {
+            Some(Json::Number(number)) => Ok(Some(*number)),
+            Some(Json::Integer(integer)) => Ok(Some(*integer as f64)),
+            Some(Json::Null) => Ok(None),
+            _ => Err(missing(key)),
+        };
+        let required = |key: &str| number(key)?.ok_or_else(|| missing(key));
+        let state = |key: &str| match object.get(key) {
+            Some(Json::Text(name)) => name.parse().map(Some).map_err(SnapshotError::Corrupt),
+            Some(Json::Null) => Ok(None),
+            _ => Err(missing(key)),
+        };
+        let positions = match object.get("positions") {
+            Some(Json::Object(members)) => members
+                .iter()
+                .map(|(id, size)| match size {
+                    Json::Number(size) => Ok((id.clone(), *size)),
+                    Json::Integer(size) => Ok((id.clone(), *size as f64)),
+                    _ => Err(SnapshotError::Corrupt(format!("bad size for {}", id))),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(missing("positions")),
+        };
+        let es_limits = match (number("es_warning_level")?, number("es_limit")?) {
+            (Some(warning_level), Some(limit)) => Some(MetricLimits { warning_level, limit }),
+            _ => None,
+        };
+        Ok(RiskSnapshot {
+            taken_at: UNIX_EPOCH + Duration::from_nanos(integer("taken_at_ns")?),
+            state: state("state")?.ok_or_else(|| missing("state"))?,
+            state_entered_at: UNIX_EPOCH + Duration::from_nanos(integer("state_entered_at_ns")?),
+            escalated: integer("escalated")? != 0,
+            resume: state("resume")?,
+            var_limit: required("var_limit")?,
+            warning_level: required("warning_level")?,
+            shutdown_multiplier: required("shutdown_multiplier")?,
+            warning_band: required("warning_band")?,
+            limit_band: required("limit_band")?,
+            es_limits,
+            throttle: ThrottleLimits {
+                max_order_size: required("max_order_size")?,
+                max_orders_per_second: integer("max_orders_per_second")? as u32,
+            },
+            positions,
+            next_sequence: integer("next_sequence")?,
+        })
+    }
+}
+
+/// Where and how often `RiskManager` writes its snapshot.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpointer {
+    pub(crate) path: PathBuf,
+    pub(crate) interval: Duration,
+    pub(crate) last: Option<SystemTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip_and_corruption() {
+        let snapshot = RiskSnapshot {
+            taken_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            state: StateKind::EngineUnavailable,
+            state_entered_at: UNIX_EPOCH + Duration::from_secs(1_699_999_000),
+            escalated: false,
+            resume: Some(StateKind::Shutdown),
+            var_limit: 100.0,
+            warning_level: 80.0,
+            shutdown_multiplier: 1.5,
+            warning_band: 5.0,
+            limit_band: 10.0,
+            es_limits: Some(MetricLimits { warning_level: 90.0, limit: 120.0 }),
+            throttle: ThrottleLimits::default(),
+            positions: BTreeMap::from([("Position \"1\"".to_string(), 12.5), ("Position2".to_string(), -3.0)]),
+            next_sequence: 42,
+        };
+        let path = std::env::temp_dir().join(format!("risk_snapshot_{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        assert_eq!(RiskSnapshot::load(&path).unwrap(), snapshot);
+        std::fs::remove_file(&path).unwrap();
+
+        let tampered = snapshot.encode().replace("12.5", "1.5");
+        assert!(matches!(RiskSnapshot::decode(&tampered), Err(SnapshotError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::sync::{Arc, RwLock};
+
+use super::attribution::{PositionAttribution, VarAttribution};
+
+/// Portfolio risk figures produced by a `VarEngine`, both expressed as positive losses.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RiskMeasures {
+    pub var: f64,
+    pub expected_shortfall: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RiskMetric {
+    Var,
+    ExpectedShortfall,
+}
+
+impl fmt::Display for RiskMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskMetric::Var => write!(f, "VaR"),
+            RiskMetric::ExpectedShortfall => write!(f, "ES"),
+        }
+    }
+}
+
+/// Warning level and hard limit for one risk metric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricLimits {
+    pub warning_level: f64,
+    pub limit: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarError {
+    UnknownPosition(String),
+    UnknownFactor(String),
+    InvalidParameter(String),
+    InvalidData(String),
+}
+
+impl fmt::Display for VarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarError::UnknownPosition(id) => write!(f, "no risk data for position '{}'", id),
+            VarError::UnknownFactor(name) => write!(f, "unknown risk factor '{}'", name),
+            VarError::InvalidParameter(msg) => write!(f, "invalid parameter: {}", msg),
+            VarError::InvalidData(msg) => write!(f, "invalid data: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for VarError {}
+
+/// Computes portfolio risk for the book held by `RiskManager` (position id -> size).
+pub trait VarEngine: fmt::Debug {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError>;
+
+    /// Marginal, incremental and component VaR per position.
+    ///
+    /// Marginal VaR is a central finite difference of `measure`; component VaR is rescaled so
+    /// that the components add up to the total exactly.
+    fn attribute(&self, positions: &HashMap<String, f64>) -> Result<VarAttribution, VarError> {
+        const BUMP: f64 = 1e-4;
+        let total_var = self.measure(positions)?.var;
+        let mut book = positions.clone();
+        let mut attributed = Vec::with_capacity(positions.len());
+        for (id, size) in positions {
+            let step = if *size == 0.0 { BUMP } else { size.abs() * BUMP };
+            book.insert(id.clone(), size + step);
+            let up = self.measure(&book)?.var;
+            book.insert(id.clone(), size - step);
+            let down = self.measure(&book)?.var;
+            book.remove(id);
+            let without = self.measure(&book)?.var;
+            book.insert(id.clone(), *size);
+
+            let marginal_var = (up - down) / (2.0 * step);
+            attributed.push(PositionAttribution {
+                position_id: id.clone(),
+                size: *size,
+                marginal_var,
+                incremental_var: total_var - without,
+                component_var: size * marginal_var,
+            });
+        }
+        let euler_total: f64 = attributed.iter().map(|p| p.component_var).sum();
+        if euler_total != 0.0 {
+            for position in &mut attributed {
+                position.component_var *= total_var / euler_total;
+            }
+        }
+        attributed.sort_by(|a, b| b.component_var.total_cmp(&a.component_var));
+        Ok(VarAttribution { total_var, positions: attributed })
+    }
+}
+
+/// Adds up the per-position VaR contributions, i.e. assumes every position is perfectly correlated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SummedVar;
+
+impl VarEngine for SummedVar {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError> {
+        Ok(RiskMeasures {
+            var: positions.values().sum(),
+            expected_shortfall: None,
+        })
+    }
+}
+
+/// Historical risk factor moves, one row per scenario, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioSet {
+    pub factors: Vec<String>,
+    pub scenarios: Vec<Vec<f64>>,
+}
+
+impl ScenarioSet {
+    /// Reads a CSV file with a header of factor names. A leading `date` or `scenario` column is ignored.
+    pub fn from_csv(reader: impl BufRead) -> Result<Self, VarError> {
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(line) => line.map_err(|err| VarError::InvalidData(err.to_string()))?,
+            None => return Err(VarError::InvalidData("empty scenario file".to_string())),
+        };
+        let mut factors: Vec<String> = header.split(',').map(|name| name.trim().to_string()).collect();
+        let skip = matches!(factors.first().map(|name| name.to_ascii_lowercase()).as_deref(), Some("date" | "scenario"));
+        if skip {
+            factors.remove(0);
+        }
+
+        let mut scenarios = Vec::new();
+        for (row, line) in lines.enumerate() {
+            let line = line.map_err(|err| VarError::InvalidData(err.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values = line
+                .split(',')
+                .skip(usize::from(skip))
+                .map(|cell| cell.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|err| VarError::InvalidData(format!("row {}: {}", row + 2, err)))?;
+            if values.len() != factors.len() {
+                return Err(VarError::InvalidData(format!(
+                    "row {} has {} values, expected {}",
+                    row + 2,
+                    values.len(),
+                    factors.len()
+                )));
+            }
+            scenarios.push(values);
+        }
+        Ok(ScenarioSet { factors, scenarios })
+    }
+
+    /// P&L of one unit of a position with the given factor sensitivities, per scenario.
+    pub fn pnl(&self, sensitivities: &[(&str, f64)]) -> Result<Vec<f64>, VarError> {
+        let mut weights = vec![0.0; self.factors.len()];
+        for (factor, sensitivity) in sensitivities {
+            let idx = self
+                .factors
+                .iter()
+                .position(|name| name == factor)
+                .ok_or_else(|| VarError::UnknownFactor(factor.to_string()))?;
+            weights[idx] += sensitivity;
+        }
+        Ok(self
+            .scenarios
+            .iter()
+            .map(|moves| moves.iter().zip(&weights).map(|(dx, w)| dx * w).sum())
+            .collect())
+    }
+}
+
+/// Historical-simulation VaR: the confidence quantile of the portfolio P&L over the lookback window.
+#[derive(Debug, Clone)]
+pub struct HistoricalVar {
+    confidence: f64,
+    lookback: usize,
+    short_history: bool,
+    scenarios: Option<ScenarioSet>,
+    pnl: HashMap<String, Vec<f64>>,
+}
+
+impl HistoricalVar {
+    pub fn new(confidence: f64, lookback: usize) -> Result<Self, VarError> {
+        check_confidence(confidence)?;
+        if lookback == 0 {
+            return Err(VarError::InvalidParameter("lookback must be at least one scenario".to_string()));
+        }
+        Ok(HistoricalVar {
+            confidence,
+            lookback,
+            short_history: false,
+            scenarios: None,
+            pnl: HashMap::new(),
+        })
+    }
+
+    /// Shortens the window to the shortest held history instead of failing when a position has
+    /// fewer than `lookback` observations.
+    pub fn with_short_history(mut self) -> Self {
+        self.short_history = true;
+        self
+    }
+
+    pub fn with_scenarios(mut self, scenarios: ScenarioSet) -> Self {
+        self.scenarios = Some(scenarios);
+        self
+    }
+
+    /// Registers the historical P&L of one unit of a position, oldest first.
+    pub fn with_pnl(mut self, position_id: &str, pnl: Vec<f64>) -> Self {
+        self.pnl.insert(position_id.to_string(), pnl);
+        self
+    }
+
+    /// Registers a position through its sensitivities to the factors of the scenario set.
+    pub fn with_sensitivities(mut self, position_id: &str, sensitivities: &[(&str, f64)]) -> Result<Self, VarError> {
+        let pnl = self
+            .scenarios
+            .as_ref()
+            .ok_or_else(|| VarError::InvalidData("no scenario set loaded".to_string()))?
+            .pnl(sensitivities)?;
+        self.pnl.insert(position_id.to_string(), pnl);
+        Ok(self)
+    }
+
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    pub fn lookback(&self) -> usize {
+        self.lookback
+    }
+
+    /// Portfolio P&L per scenario over the lookback window, oldest first.
+    pub fn portfolio_pnl(&self, positions: &HashMap<String, f64>) -> Result<Vec<f64>, VarError> {
+        let mut held = Vec::with_capacity(positions.len());
+        for (id, size) in positions {
+            let pnl = self.pnl.get(id).ok_or_else(|| VarError::UnknownPosition(id.clone()))?;
+            if pnl.len() < self.lookback && !self.short_history {
+                return Err(VarError::InvalidData(format!(
+                    "position '{}' has {} P&L observations, the lookback needs {}",
+                    id,
+                    pnl.len(),
+                    self.lookback
+                )));
+            }
+            held.push((*size, pnl));
+        }
+        let window = held
+            .iter()
+            .map(|(_, pnl)| pnl.len())
+            .min()
+            .unwrap_or(0)
+            .min(self.lookback);
+        let mut portfolio = vec![0.0; window];
+        for (size, pnl) in held {
+            let recent = &pnl[pnl.len() - window..];
+            for (total, value) in portfolio.iter_mut().zip(recent) {
+                *total += size * value;
+            }
+        }
+        Ok(portfolio)
+    }
+}
+
+impl VarEngine for HistoricalVar {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError> {
+        if positions.is_empty() {
+            return Ok(RiskMeasures::default());
+        }
+        Ok(tail_measures(&self.portfolio_pnl(positions)?, self.confidence))
+    }
+}
+
+/// Symmetric, positive semi-definite covariance of risk factor moves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CovarianceMatrix {
+    factors: Vec<String>,
+    values: Vec<Vec<f64>>,
+}
+
+impl CovarianceMatrix {
+    pub fn new(factors: &[&str], values: Vec<Vec<f64>>) -> Result<Self, VarError> {
+        let n = factors.len();
+        if values.len() != n || values.iter().any(|row| row.len() != n) {
+            return Err(VarError::InvalidData(format!("covariance matrix must be {}x{}", n, n)));
+        }
+        if values.iter().flatten().any(|value| !value.is_finite()) {
+            return Err(VarError::InvalidData("covariance matrix entries must be finite".to_string()));
+        }
+        let scale = values.iter().enumerate().map(|(i, row)| row[i].abs()).fold(1.0, f64::max);
+        let tolerance = 1e-10 * scale;
+        for (i, row) in values.iter().enumerate() {
+            for (j, value) in row.iter().enumerate().take(i) {
+                if (value - values[j][i]).abs() > tolerance {
+                    return Err(VarError::InvalidData(format!("covariance matrix is not symmetric at ({}, {})", i, j)));
+                }
+            }
+        }
+        if !is_positive_semi_definite(&values, tolerance) {
+            return Err(VarError::InvalidData("covariance matrix is not positive semi-definite".to_string()));
+        }
+        Ok(CovarianceMatrix {
+            factors: factors.iter().map(|name| name.to_string()).collect(),
+            values,
+        })
+    }
+
+    /// Builds the matrix from per-factor volatilities and a correlation matrix.
+    pub fn from_correlation(factors: &[&str], volatilities: &[f64], correlation: Vec<Vec<f64>>) -> Result<Self, VarError> {
+        let n = factors.len();
+        if volatilities.len() != n {
+            return Err(VarError::InvalidData("one volatility per factor is required".to_string()));
+        }
+        if volatilities.iter().any(|volatility| !volatility.is_finite() || *volatility < 0.0) {
+            return Err(VarError::InvalidData("volatilities must be finite and non-negative".to_string()));
+        }
+        if correlation.len() != n || correlation.iter().any(|row| row.len() != n) {
+            return Err(VarError::InvalidData(format!("correlation matrix must be {}x{}", n, n)));
+        }
+        let values = correlation
+            .iter()
+            .enumerate()
+            .map(|(i, row)| row.iter().enumerate().map(|(j, rho)| rho * volatilities[i] * volatilities[j]).collect())
+            .collect();
+        CovarianceMatrix::new(factors, values)
+    }
+
+    pub fn factors(&self) -> &[String] {
+        &self.factors
+    }
+
+    fn index(&self, factor: &str) -> Result<usize, VarError> {
+        self.factors
+            .iter()
+            .position(|name| name == factor)
+            .ok_or_else(|| VarError::UnknownFactor(factor.to_string()))
+    }
+
+    /// `w' Σ w`
+    fn quadratic_form(&self, weights: &[f64]) -> f64 {
+        self.values
+            .iter()
+            .zip(weights)
+            .map(|(row, wi)| wi * row.iter().zip(weights).map(|(cov, wj)| cov * wj).sum::<f64>())
+            .sum()
+    }
+}
+
+/// LDL' decomposition without pivoting; a zero pivot is only allowed if the rest of its column is zero too.
+fn is_positive_semi_definite(values: &[Vec<f64>], tolerance: f64) -> bool {
+    let n = values.len();
+    let mut l = vec![vec![0.0; n]; n];
+    let mut d = vec![0.0; n];
+    for j in 0..n {
+        d[j] = values[j][j] - (0..j).map(|k| l[j][k] * l[j][k] * d[k]).sum::<f64>();
+        if d[j] < -tolerance {
+            return false;
+        }
+        for i in j + 1..n {
+            let v = values[i][j] - (0..j).map(|k| l[i][k] * l[j][k] * d[k]).sum::<f64>();
+            if d[j] <= tolerance {
+                if v.abs() > tolerance {
+                    return false;
+                }
+            } else {
+                l[i][j] = v / d[j];
+            }
+        }
+        if d[j] <= tolerance {
+            d[j] = 0.0;
+        }
+    }
+    true
+}
+
+/// Shared handle to the covariance matrix of a `ParametricVar`, for intraday updates.
+#[derive(Debug, Clone)]
+pub struct CovarianceHandle(Arc<RwLock<CovarianceMatrix>>);
+
+impl CovarianceHandle {
+    /// Swaps in a new matrix. Call `RiskManager::revalue` afterwards to re-check the limits.
+    pub fn update(&self, matrix: CovarianceMatrix) {
+        *self.0.write().unwrap() = matrix;
+    }
+
+    pub fn current(&self) -> CovarianceMatrix {
+        self.0.read().unwrap().clone()
+    }
+}
+
+/// Variance-covariance VaR: `z * sqrt(w' Σ w)` where `w` is the book's net exposure to each risk factor.
+#[derive(Debug, Clone)]
+pub struct ParametricVar {
+    confidence: f64,
+    covariance: CovarianceHandle,
+    exposures: HashMap<String, Vec<(String, f64)>>,
+}
+
+impl ParametricVar {
+    pub fn new(confidence: f64, covariance: CovarianceMatrix) -> Result<Self, VarError> {
+        check_confidence(confidence)?;
+        Ok(ParametricVar {
+            confidence,
+            covariance: CovarianceHandle(Arc::new(RwLock::new(covariance))),
+            exposures: HashMap::new(),
+        })
+    }
+
+    /// Registers the risk factor exposures of one unit of a position.
+    pub fn with_exposures(mut self, position_id: &str, exposures: &[(&str, f64)]) -> Self {
+        self.exposures.insert(
+            position_id.to_string(),
+            exposures.iter().map(|(factor, exposure)| (factor.to_string(), *exposure)).collect(),
+        );
+        self
+    }
+
+    pub fn covariance_handle(&self) -> CovarianceHandle {
+        self.covariance.clone()
+    }
+}
+
+impl VarEngine for ParametricVar {
+    fn measure(&self, positions: &HashMap<String, f64>) -> Result<RiskMeasures, VarError> {
+        let covariance = self.covariance.0.read().unwrap();
+        let mut weights = vec![0.0; covariance.factors.len()];
+        for (id, size) in positions {
+            let exposures = self.exposures.get(id).ok_or_else(|| VarError::UnknownPosition(id.clone()))?;
+            for (factor, exposure) in exposures {
+                weights[covariance.index(factor)?] += size * exposure;
+            }
+        }
+        let sigma = covariance.quadratic_form(&weights).max(0.0).sqrt();
+        let z = normal_quantile(self.confidence);
+        Ok(RiskMeasures {
+            var: z * sigma,
+            expected_shortfall: Some(sigma * normal_pdf(z) / (1.0 - self.confidence)),
+        })
+    }
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// Inverse of the standard normal CDF (Acklam's rational approximation, relative error < 1.2e-9).
+pub(crate) fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02, 1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00];
+    const B: [f64; 5] = [-5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02, 6.680131188771972e+01, -1.328068155288572e+01];
+    const C: [f64; 6] = [-7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00, -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00];
+    const D: [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00, 3.754408661907416e+00];
+    const P_LOW: f64 = 0.02425;
+
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -normal_quantile(1.0 - p)
+    }
+}
+
+pub(crate) fn check_confidence(confidence: f64) -> Result<(), VarError> {
+    if confidence > 0.0 && confidence < 1.0 {
+        Ok(())
+    } else {
+        Err(VarError::InvalidParameter(format!("confidence {} must be between 0 and 1", confidence)))
+    }
+}
+
+/// VaR and Expected Shortfall of a P&L sample at the given confidence, floored at zero.
+pub(crate) fn tail_measures(pnl: &[f64], confidence: f64) -> RiskMeasures {
+    if pnl.is_empty() {
+        return RiskMeasures::default();
+    }
+    let mut losses: Vec<f64> = pnl.iter().map(|value| -value).collect();
+    losses.sort_by(|a, b| a.total_cmp(b));
+    let idx = ((confidence * losses.len() as f64).ceil() as usize).clamp(1, losses.len()) - 1;
+    let tail = &losses[idx..];
+    RiskMeasures {
+        var: losses[idx].max(0.0),
+        expected_shortfall: Some((tail.iter().sum::<f64>() / tail.len() as f64).max(0.0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_historical_var_quantile_and_lookback() {
+        // Unit losses 1..=100, oldest first, plus ten older scenarios that fall outside the lookback.
+        let mut pnl: Vec<f64> = vec![-1000.0; 10];
+        pnl.extend((1..=100).map(|loss| -(loss as f64)));
+        let engine = HistoricalVar::new(0.95, 100).unwrap().with_pnl("Bond", pnl);
+        let positions = HashMap::from([("Bond".to_string(), 2.0)]);
+
+        let measures = engine.measure(&positions).unwrap();
+        assert_eq!(measures.var, 190.0);
+        assert_eq!(measures.expected_shortfall, Some(195.0));
+        assert_eq!(
+            engine.measure(&HashMap::from([("Swap".to_string(), 1.0)])),
+            Err(VarError::UnknownPosition("Swap".to_string()))
+        );
+
+        // A newly listed position does not silently shrink the window to its own history.
+        let engine = engine.with_pnl("Listed", vec![-5.0; 5]);
+        let book = HashMap::from([("Bond".to_string(), 2.0), ("Listed".to_string(), 1.0)]);
+        assert!(matches!(engine.measure(&book), Err(VarError::InvalidData(_))));
+        assert_eq!(engine.with_short_history().portfolio_pnl(&book).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_scenarios_from_csv_with_sensitivities() {
+        let csv = "date,rates,equity\n2024-01-02,0.01,-2.0\n2024-01-03,-0.02,1.0\n2024-01-04,0.03,-4.0\n";
+        let scenarios = ScenarioSet::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(scenarios.factors, vec!["rates", "equity"]);
+
+        let engine = HistoricalVar::new(0.99, 250)
+            .unwrap()
+            .with_short_history()
+            .with_scenarios(scenarios)
+            .with_sensitivities("Hedged", &[("rates", 100.0), ("equity", 1.0)])
+            .unwrap();
+        let pnl = engine.portfolio_pnl(&HashMap::from([("Hedged".to_string(), 1.0)])).unwrap();
+        assert_eq!(pnl, vec![-1.0, -1.0, -1.0]);
+        assert!(matches!(
+            engine.clone().with_sensitivities("FX", &[("eurusd", 1.0)]),
+            Err(VarError::UnknownFactor(_))
+        ));
+    }
+
+    #[test]
+    fn test_parametric_var_with_correlation() {
+        let covariance = CovarianceMatrix::from_correlation(
+            &["rates", "equity"],
+            &[2.0, 3.0],
+            vec![vec![1.0, -0.5], vec![-0.5, 1.0]],
+        )
+        .unwrap();
+        let engine = ParametricVar::new(0.99, covariance)
+            .unwrap()
+            .with_exposures("Bond", &[("rates", 10.0)])
+            .with_exposures("Stock", &[("equity", 10.0)]);
+        let positions = HashMap::from([("Bond".to_string(), 1.0), ("Stock".to_string(), 1.0)]);
+
+        // w = (10, 10): w' Σ w = 400 + 900 - 2 * 0.5 * 600 = 700
+        let z = normal_quantile(0.99);
+        assert!((z - 2.326_347_874).abs() < 1e-8);
+        let measures = engine.measure(&positions).unwrap();
+        assert!((measures.var - z * 700f64.sqrt()).abs() < 1e-9);
+        assert!(measures.var < z * 20.0 + z * 30.0);
+        assert!(measures.expected_shortfall.unwrap() > measures.var);
+
+        engine.covariance_handle().update(
+            CovarianceMatrix::new(&["rates", "equity"], vec![vec![4.0, 6.0], vec![6.0, 9.0]]).unwrap(),
+        );
+        assert!((engine.measure(&positions).unwrap().var - z * 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_covariance_matrix_must_be_positive_semi_definite() {
+        let not_psd = CovarianceMatrix::new(&["a", "b"], vec![vec![1.0, 2.0], vec![2.0, 1.0]]);
+        assert!(matches!(not_psd, Err(VarError::InvalidData(_))));
+        let asymmetric = CovarianceMatrix::new(&["a", "b"], vec![vec![1.0, 0.1], vec![0.2, 1.0]]);
+        assert!(matches!(asymmetric, Err(VarError::InvalidData(_))));
+        let singular = CovarianceMatrix::new(&["a", "b"], vec![vec![1.0, 1.0], vec![1.0, 1.0]]);
+        assert!(singular.is_ok());
+        let infinite = CovarianceMatrix::new(&["a"], vec![vec![f64::INFINITY]]);
+        assert!(matches!(infinite, Err(VarError::InvalidData(_))));
+
+        // The correlation's shape is checked before it is scaled by the volatilities.
+        let too_wide = CovarianceMatrix::from_correlation(&["a"], &[1.0], vec![vec![1.0, 0.0]]);
+        assert!(matches!(too_wide, Err(VarError::InvalidData(_))));
+        let too_tall = CovarianceMatrix::from_correlation(&["a"], &[1.0], vec![vec![1.0], vec![0.0]]);
+        assert!(matches!(too_tall, Err(VarError::InvalidData(_))));
+        let nan_volatility = CovarianceMatrix::from_correlation(&["a"], &[f64::NAN], vec![vec![1.0]]);
+        assert!(matches!(nan_volatility, Err(VarError::InvalidData(_))));
+    }
+}
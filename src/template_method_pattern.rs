@@ -52,7 +52,7 @@ impl MCSimulation for GeometricBrownianMotion {
         for &dw in random_numbers {
             let drift = self.drift(dt);
             let diffusion = self.diffusion(dt) * dw ;
-            s = s * (drift + diffusion).exp();
+            s *= (drift + diffusion).exp();
             path.push(s);
         }
         path
@@ -94,6 +94,8 @@ impl MCSimulation for Vasicek {
     }
 }
 
+// Demo entry point from when this file was a standalone binary; the crate is now a library.
+#[allow(dead_code)]
 fn main() {
     let gbm = GeometricBrownianMotion {
         initial_value: 100.0,